@@ -1,90 +1,167 @@
 use anchor_lang::prelude::*;
-use std::str::FromStr;
+use anchor_lang::system_program;
+use anchor_lang::solana_program::{ instruction::{ AccountMeta, Instruction }, program::invoke_signed };
 pub use anchor_lang::Discriminator;
+use pyth_sdk_solana::{ load_price_feed_from_account_info, Price };
 
 declare_id!("3aiTRX5dhvWfgKa1kNwqF97jpGukMyphpj7UTcWmWfvV");
 
 // Questions
 // * Should we strictly rely on Anchor events? How do we backfill results?
 // * Should we order transactions in a buffer and start flushing in order of timestamps?
-//      This would let us fulfill multiple orders in the same txn
+//      fulfill_orders lets the worker fulfill multiple orders in the same txn
 
-pub static BTC_MARKET_BYTES: [u8; 8] = [66, 84, 67, 0, 0, 0, 0, 0];
-pub static ETH_MARKET_BYTES: [u8; 8] = [69, 84, 72, 0, 0, 0, 0, 0];
-pub static SOL_MARKET_BYTES: [u8; 8] = [83, 79, 76, 0, 0, 0, 0, 0];
+#[program]
+pub mod backfill_oracle_program {
+    use super::*;
 
-#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Hash)]
-pub enum MarketType {
-    Btc,
-    Eth,
-    Sol,
-}
-impl MarketType {
-    pub fn to_bytes(&self) -> [u8; 8] {
-        match &self {
-            MarketType::Btc => BTC_MARKET_BYTES,
-            MarketType::Eth => ETH_MARKET_BYTES,
-            MarketType::Sol => SOL_MARKET_BYTES,
+    /// Create the program state account
+    pub fn initialize(ctx: Context<Initialize>, params: InitializeParams) -> Result<()> {
+        ctx.accounts.program.bump = ctx.bumps.program;
+        ctx.accounts.program.authority = ctx.accounts.authority.key();
+        ctx.accounts.program.configure(&params)
+    }
+
+    /// Create a new market account and add it to the program's market list
+    pub fn add_market(ctx: Context<AddMarket>, params: AddMarketParams) -> Result<()> {
+        if ctx.accounts.program.markets.len() >= MAX_MARKETS {
+            return Err(error!(ProgramError::MaxMarketsReached));
         }
+
+        ctx.accounts.program.markets.push(params.name);
+
+        ctx.accounts.market.bump = ctx.bumps.market;
+        ctx.accounts.market.configure(&params)?;
+        ctx.accounts.market.order_queue = ctx.accounts.order_queue.key();
+        ctx.accounts.market.price_history = ctx.accounts.price_history.key();
+
+        let mut order_queue = ctx.accounts.order_queue.load_init()?;
+        order_queue.market = ctx.accounts.market.key();
+
+        let mut price_history = ctx.accounts.price_history.load_init()?;
+        price_history.market = ctx.accounts.market.key();
+
+        emit!(MarketAddedEvent {
+            market: ctx.accounts.market.key(),
+            name: params.name,
+        });
+
+        Ok(())
     }
-}
-impl FromStr for MarketType {
-    type Err = Error;
 
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s {
-            "BTC" => Ok(MarketType::Btc),
-            "ETH" => Ok(MarketType::Eth),
-            "SOL" => Ok(MarketType::Sol),
-            _ => Err(error!(ProgramError::InvalidMarketName)),
+    /// Update a market's configs, any field left as None is unchanged
+    pub fn update_market_config(
+        ctx: Context<UpdateMarketConfig>,
+        params: UpdateMarketConfigParams
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+
+        if let Some(decimals) = params.decimals {
+            // Keep the deviation reference and the price history on the market's new scale
+            let expo = -(market.decimals as i32);
+            market.decimals = decimals;
+            market.last_price = market.scale_price(market.last_price, expo)?;
+            ctx.accounts.price_history.load_mut()?.rescale(market, expo)?;
+        }
+        if let Some(oracle_staleness_threshold) = params.oracle_staleness_threshold {
+            market.oracle_staleness_threshold = oracle_staleness_threshold;
+        }
+        if let Some(pyth_price_feed) = params.pyth_price_feed {
+            market.pyth_price_feed = pyth_price_feed;
+        }
+        if let Some(order_ttl) = params.order_ttl {
+            if order_ttl == 0 {
+                return Err(error!(ProgramError::InvalidOrderTtl));
+            }
+            market.order_ttl = order_ttl;
+        }
+        if let Some(max_price_time_deviation) = params.max_price_time_deviation {
+            market.max_price_time_deviation = max_price_time_deviation;
+        }
+        if let Some(paused) = params.paused {
+            market.paused = paused;
+        }
+        if let Some(order_fee) = params.order_fee {
+            market.order_fee = order_fee;
         }
+        if let Some(max_lookback) = params.max_lookback {
+            market.max_lookback = max_lookback;
+        }
+        if let Some(max_open_orders) = params.max_open_orders {
+            market.max_open_orders = max_open_orders;
+        }
+        if let Some(order_deposit) = params.order_deposit {
+            market.order_deposit = order_deposit;
+        }
+        if let Some(max_deviation_bps_per_second) = params.max_deviation_bps_per_second {
+            market.max_deviation_bps_per_second = max_deviation_bps_per_second;
+        }
+
+        emit!(MarketConfigUpdatedEvent {
+            market: market.key(),
+            name: market.name,
+            decimals: market.decimals,
+            oracle_staleness_threshold: market.oracle_staleness_threshold,
+            paused: market.paused,
+            order_fee: market.order_fee,
+            max_open_orders: market.max_open_orders,
+            order_deposit: market.order_deposit,
+            max_deviation_bps_per_second: market.max_deviation_bps_per_second,
+        });
+
+        Ok(())
     }
-}
-impl TryFrom<[u8; 8]> for MarketType {
-    type Error = Error;
 
-    fn try_from(bytes: [u8; 8]) -> std::result::Result<Self, Self::Error> {
-        if bytes == BTC_MARKET_BYTES {
-            Ok(MarketType::Btc)
-        } else if bytes == ETH_MARKET_BYTES {
-            Ok(MarketType::Eth)
-        } else if bytes == SOL_MARKET_BYTES {
-            Ok(MarketType::Sol)
-        } else {
-            Err(error!(ProgramError::InvalidMarketName))
+    /// Update the program configs, any field left as None is unchanged
+    pub fn update_program_config(
+        ctx: Context<UpdateProgramConfig>,
+        params: UpdateProgramConfigParams
+    ) -> Result<()> {
+        let program = &mut ctx.accounts.program;
+
+        if let Some(oracle_quorum) = params.oracle_quorum {
+            if oracle_quorum == 0 || (oracle_quorum as usize) > program.oracles.len() {
+                return Err(error!(ProgramError::InvalidOracleQuorum));
+            }
+            program.oracle_quorum = oracle_quorum;
+        }
+        if let Some(min_stake) = params.min_stake {
+            if min_stake == 0 {
+                return Err(error!(ProgramError::InvalidStakeAmount));
+            }
+            program.min_stake = min_stake;
         }
+
+        emit!(ProgramConfigUpdatedEvent {
+            oracle_quorum: program.oracle_quorum,
+            num_oracles: program.oracles.len() as u8,
+            min_stake: program.min_stake,
+        });
+
+        Ok(())
     }
-}
 
-#[program]
-pub mod backfill_oracle_program {
-    use super::*;
+    /// Nominate a new program authority, which must call accept_authority to complete the transfer
+    pub fn transfer_authority(
+        ctx: Context<TransferAuthority>,
+        params: TransferAuthorityParams
+    ) -> Result<()> {
+        ctx.accounts.program.pending_authority = params.new_authority;
 
-    /// Create all of the program/oracle accounts
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        ctx.accounts.program.bump = ctx.bumps.program;
-        ctx.accounts.program.authority = ctx.accounts.authority.key();
+        Ok(())
+    }
 
-        let btc_market_name: [u8; 8] = BTC_MARKET_BYTES;
-        ctx.accounts.program.markets.push(btc_market_name);
-        ctx.accounts.btc_market.bump = ctx.bumps.btc_market;
-        ctx.accounts.btc_market.name = btc_market_name;
-        ctx.accounts.btc_market.decimals = 9;
-        ctx.accounts.btc_market.oracle_staleness_threshold = 30;
+    /// Complete an authority transfer as the pending authority
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let program = &mut ctx.accounts.program;
 
-        let eth_market_name: [u8; 8] = ETH_MARKET_BYTES;
-        ctx.accounts.program.markets.push(eth_market_name);
-        ctx.accounts.eth_market.bump = ctx.bumps.eth_market;
-        ctx.accounts.eth_market.name = eth_market_name;
-        ctx.accounts.eth_market.decimals = 9;
-        ctx.accounts.eth_market.oracle_staleness_threshold = 30;
+        emit!(AuthorityTransferredEvent {
+            previous_authority: program.authority,
+            new_authority: program.pending_authority,
+        });
 
-        let sol_market_name: [u8; 8] = SOL_MARKET_BYTES;
-        ctx.accounts.program.markets.push(sol_market_name);
-        ctx.accounts.sol_market.bump = ctx.bumps.sol_market;
-        ctx.accounts.sol_market.name = sol_market_name;
-        ctx.accounts.sol_market.decimals = 9;
-        ctx.accounts.sol_market.oracle_staleness_threshold = 30;
+        program.authority = program.pending_authority;
+        program.pending_authority = Pubkey::default();
 
         Ok(())
     }
@@ -92,272 +169,3313 @@ pub mod backfill_oracle_program {
     /// Create a dummy oracle and register the secure signer
     pub fn register_oracle(ctx: Context<RegisterOracle>) -> Result<()> {
         if ctx.accounts.oracle.bump == 0 {
-            ctx.accounts.program.oracle = ctx.accounts.oracle.key();
-
             ctx.accounts.oracle.bump = ctx.bumps.oracle;
             ctx.accounts.oracle.authority = ctx.accounts.authority.key();
-            ctx.accounts.oracle.verification_timestamp = Clock::get()?.unix_timestamp;
-            ctx.accounts.oracle.verification_slot = Clock::get()?.slot;
-            ctx.accounts.oracle.valid_until_slot = u64::MAX;
         }
 
         ctx.accounts.oracle.enclave_signer = ctx.accounts.enclave_signer.key();
+        ctx.accounts.oracle.attest(ctx.accounts.program.attestation_validity_slots)?;
+
+        Ok(())
+    }
+
+    /// Add a registered oracle to the set of oracles allowed to submit prices. The oracle must
+    /// have deposited the program's min_stake first.
+    pub fn add_oracle(ctx: Context<AddOracle>, params: AddOracleParams) -> Result<()> {
+        if ctx.accounts.program.oracles.contains(&params.oracle) {
+            return Err(error!(ProgramError::OracleAlreadyRegistered));
+        }
+
+        if ctx.accounts.program.oracles.len() >= MAX_ORACLES {
+            return Err(error!(ProgramError::MaxOraclesReached));
+        }
+
+        ctx.accounts.program.oracles.push(params.oracle);
+
+        Ok(())
+    }
+
+    /// Remove an oracle so it can no longer submit prices. The quorum must be lowered first if
+    /// there would be fewer oracles than it.
+    pub fn remove_oracle(ctx: Context<RemoveOracle>, params: RemoveOracleParams) -> Result<()> {
+        let program = &mut ctx.accounts.program;
+        let idx = program.oracles
+            .iter()
+            .position(|o| *o == params.oracle)
+            .ok_or(error!(ProgramError::OracleNotRegistered))?;
+
+        if program.oracles.len() <= (program.oracle_quorum as usize) {
+            return Err(error!(ProgramError::InsufficientOracles));
+        }
+
+        program.oracles.remove(idx);
+
+        Ok(())
+    }
+
+    /// Add a challenger that is allowed to dispute fulfilled orders
+    pub fn add_challenger(ctx: Context<AddChallenger>, params: AddChallengerParams) -> Result<()> {
+        if ctx.accounts.program.challengers.contains(&params.challenger) {
+            return Err(error!(ProgramError::ChallengerAlreadyRegistered));
+        }
+
+        if ctx.accounts.program.challengers.len() >= MAX_CHALLENGERS {
+            return Err(error!(ProgramError::MaxChallengersReached));
+        }
+
+        ctx.accounts.program.challengers.push(params.challenger);
+
+        Ok(())
+    }
+
+    /// Remove a challenger so it can no longer dispute orders
+    pub fn remove_challenger(
+        ctx: Context<RemoveChallenger>,
+        params: RemoveChallengerParams
+    ) -> Result<()> {
+        let challengers = &mut ctx.accounts.program.challengers;
+        let idx = challengers
+            .iter()
+            .position(|c| *c == params.challenger)
+            .ok_or(error!(ProgramError::ChallengerNotRegistered))?;
+        challengers.remove(idx);
+
+        Ok(())
+    }
+
+    /// Deposit lamports into the oracle's stake account, which can be slashed if a dispute
+    /// against one of its submissions is upheld
+    pub fn deposit_stake(ctx: Context<DepositStake>, params: DepositStakeParams) -> Result<()> {
+        if ctx.accounts.stake.bump == 0 {
+            ctx.accounts.stake.bump = ctx.bumps.stake;
+            ctx.accounts.stake.oracle = ctx.accounts.oracle.key();
+        }
+
+        system_program::transfer(
+            CpiContext::new(ctx.accounts.system_program.to_account_info(), system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: ctx.accounts.stake.to_account_info(),
+            }),
+            params.amount
+        )?;
+
+        ctx.accounts.stake.amount = ctx.accounts.stake.amount
+            .checked_add(params.amount)
+            .ok_or(error!(ProgramError::InvalidStakeAmount))?;
+
+        emit!(StakeDepositedEvent {
+            oracle: ctx.accounts.oracle.key(),
+            amount: params.amount,
+            total: ctx.accounts.stake.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw stake as the oracle authority. Stake is locked while a dispute is pending or
+    /// any of the oracle's submissions can still be disputed.
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, params: WithdrawStakeParams) -> Result<()> {
+        let stake = &mut ctx.accounts.stake;
+
+        if stake.pending_disputes > 0 || Clock::get()?.unix_timestamp <= stake.locked_until {
+            return Err(error!(ProgramError::StakeLocked));
+        }
+
+        if params.amount == 0 || params.amount > stake.amount {
+            return Err(error!(ProgramError::InvalidStakeAmount));
+        }
+
+        stake.sub_lamports(params.amount)?;
+        ctx.accounts.destination.add_lamports(params.amount)?;
+        stake.amount -= params.amount;
+
+        Ok(())
+    }
+
+    /// Re-attest the enclave signer and extend the oracle's validity window
+    pub fn heartbeat(ctx: Context<Heartbeat>) -> Result<()> {
+        ctx.accounts.oracle.attest(ctx.accounts.program.attestation_validity_slots)?;
+
+        emit!(OracleHeartbeatEvent {
+            oracle: ctx.accounts.oracle.key(),
+            enclave_signer: ctx.accounts.enclave_signer.key(),
+            valid_until_slot: ctx.accounts.oracle.valid_until_slot,
+        });
 
         Ok(())
     }
 
     /// Create an order, read the oracle price, and emit an event if the price is stale
     pub fn create_order(ctx: Context<CreateOrder>, params: CreateOrderParams) -> Result<()> {
-        ctx.accounts.order.open_order = 1;
-        ctx.accounts.order.authority = ctx.accounts.authority.key();
-        ctx.accounts.order.market = ctx.accounts.market.key();
-        ctx.accounts.order.market_name = params.market.to_bytes();
-        ctx.accounts.order.open_timestamp = Clock::get()?.unix_timestamp;
-        ctx.accounts.order.open_slot = Clock::get()?.slot;
-
-        emit!(OraclePriceRequestedEvent {
-            oracle: ctx.accounts.program.oracle,
+        let clock = Clock::get()?;
+        let market = &ctx.accounts.new_order.market;
+
+        // Orders are priced at the current time unless the caller asks for a past timestamp
+        let requested_timestamp = match params.timestamp {
+            Some(timestamp) => {
+                market.check_lookback(timestamp, clock.unix_timestamp)?;
+                timestamp
+            }
+            None => clock.unix_timestamp,
+        };
+
+        params.kind.validate()?;
+
+        let price_feed = load_price_feed_from_account_info(&ctx.accounts.pyth_price).map_err(
+            |_| error!(ProgramError::InvalidPythPriceFeed)
+        )?;
+
+        // If the Pyth price is fresh we can settle the order immediately, no backfill needed.
+        // Historical orders always go to the oracles, as do orders with a callback since it's
+        // only invoked by fulfill_order once the order account exists.
+        let settle_from_pyth =
+            requested_timestamp == clock.unix_timestamp && params.callback.is_none();
+        let pyth_price = if settle_from_pyth {
+            price_feed.get_price_no_older_than(
+                clock.unix_timestamp,
+                market.oracle_staleness_threshold.into()
+            )
+        } else {
+            None
+        };
+
+        ctx.accounts.new_order.create(
+            &ctx.bumps.new_order,
+            requested_timestamp,
+            0,
+            0,
+            params.kind,
+            params.callback.as_ref(),
+            pyth_price,
+            &clock,
+            &EventAuthority::new(&ctx.accounts.event_authority, ctx.bumps.event_authority)
+        )
+    }
+
+    /// Create an order for the time-weighted average price over a window starting now. The
+    /// oracles fulfill it with the average of the per-second prices once the window has elapsed.
+    pub fn create_twap_order(
+        ctx: Context<CreateTwapOrder>,
+        params: CreateTwapOrderParams
+    ) -> Result<()> {
+        if params.window == 0 || params.window > MAX_TWAP_WINDOW {
+            return Err(error!(ProgramError::InvalidTwapWindow));
+        }
+
+        let clock = Clock::get()?;
+
+        ctx.accounts.new_order.create(
+            &ctx.bumps.new_order,
+            clock.unix_timestamp,
+            params.window,
+            0,
+            OrderKind::Price,
+            params.callback.as_ref(),
+            None,
+            &clock,
+            &EventAuthority::new(&ctx.accounts.event_authority, ctx.bumps.event_authority)
+        )
+    }
+
+    /// Create an order for the OHLC candle of a market over [start, end]. The oracles fulfill it
+    /// once the interval has closed and the candle's close is stored as the order's price.
+    pub fn create_candle_order(
+        ctx: Context<CreateCandleOrder>,
+        params: CreateCandleOrderParams
+    ) -> Result<()> {
+        if params.end <= params.start || params.end - params.start > MAX_CANDLE_INTERVAL {
+            return Err(error!(ProgramError::InvalidCandleInterval));
+        }
+
+        let clock = Clock::get()?;
+        ctx.accounts.new_order.market.check_lookback(params.start, clock.unix_timestamp)?;
+
+        ctx.accounts.new_order.create(
+            &ctx.bumps.new_order,
+            params.start,
+            0,
+            params.end,
+            OrderKind::Price,
+            params.callback.as_ref(),
+            None,
+            &clock,
+            &EventAuthority::new(&ctx.accounts.event_authority, ctx.bumps.event_authority)
+        )
+    }
+
+    /// Submit a backfilled price and settle the order to the median once the quorum is reached.
+    /// If the order has a callback, the remaining accounts are the callback program, its callback
+    /// authority and the callback accounts, and the callback is invoked once the order settles.
+    pub fn fulfill_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FulfillOrder<'info>>,
+        params: FulfillOrderParams
+    ) -> Result<()> {
+        let order_key = ctx.accounts.order.key();
+
+        let settled_with_callback = {
+            let mut order = ctx.accounts.order.load_mut()?;
+            order.submit_price(
+                order_key,
+                &mut ctx.accounts.market,
+                &mut *ctx.accounts.order_queue.load_mut()?,
+                &mut *ctx.accounts.price_history.load_mut()?,
+                params.to_submission(ctx.accounts.oracle.key()),
+                &EventAuthority::new(&ctx.accounts.event_authority, ctx.bumps.event_authority)
+            )?;
+            ctx.accounts.oracle.stats.record(&order, &Clock::get()?);
+            ctx.accounts.stake.lock_for_submission(
+                &order,
+                ctx.accounts.program_config.dispute_window
+            );
+
+            let fee = order.take_fee_share();
+            pay_oracle_fee(ctx.accounts.order.as_ref(), &mut ctx.accounts.oracle, fee)?;
+
+            if order.open_order == 0 && order.rate_limited == 1 {
+                release_rate_limit(&ctx.accounts.rate_limit, &order)?;
+            }
+
+            order.open_order == 0 && order.has_callback == 1
+        };
+
+        if settled_with_callback {
+            invoke_callback(&ctx.accounts.order, &ctx.accounts.market, ctx.remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Submit backfilled prices for many orders in one transaction. The remaining accounts are
+    /// a writable (order, market, order_queue, price_history, rate_limit) group for each entry in
+    /// params.orders, where rate_limit is the order authority's RateLimitAccount in the market.
+    pub fn fulfill_orders<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FulfillOrders<'info>>,
+        params: FulfillOrdersParams
+    ) -> Result<()> {
+        if
+            params.orders.is_empty() ||
+            ctx.remaining_accounts.len() != params.orders.len() * 5
+        {
+            return Err(error!(ProgramError::InvalidRemainingAccounts));
+        }
+
+        let oracle = ctx.accounts.oracle.key();
+        let event_authority = EventAuthority::new(
+            &ctx.accounts.event_authority,
+            ctx.bumps.event_authority
+        );
+
+        for (i, order_params) in params.orders.iter().enumerate() {
+            let order_info = &ctx.remaining_accounts[i * 5];
+            let market_info = &ctx.remaining_accounts[i * 5 + 1];
+            let order_queue_info = &ctx.remaining_accounts[i * 5 + 2];
+            let price_history_info = &ctx.remaining_accounts[i * 5 + 3];
+            let rate_limit_info = &ctx.remaining_accounts[i * 5 + 4];
+
+            if
+                !order_info.is_writable ||
+                !market_info.is_writable ||
+                !order_queue_info.is_writable ||
+                !price_history_info.is_writable ||
+                !rate_limit_info.is_writable
+            {
+                return Err(error!(ProgramError::InvalidRemainingAccounts));
+            }
+
+            let mut market = Account::<MarketAccount>::try_from(market_info)?;
+            if market.name != order_params.market {
+                return Err(error!(ProgramError::InvalidMarketName));
+            }
+            if
+                market.order_queue != order_queue_info.key() ||
+                market.price_history != price_history_info.key()
+            {
+                return Err(error!(ProgramError::InvalidRemainingAccounts));
+            }
+
+            let order_loader = AccountLoader::<OrderAccount>::try_from(order_info)?;
+            if !OrderAccount::is_current(order_info)? {
+                return Err(error!(ProgramError::OrderNotMigrated));
+            }
+            let mut order = order_loader.load_mut()?;
+            if order.market != market.key() {
+                return Err(error!(ProgramError::InvalidRemainingAccounts));
+            }
+
+            // Another oracle may have completed the quorum since the batch was built
+            if order.open_order != 1 {
+                msg!("Skipping fulfilled order {}", order_info.key());
+                continue;
+            }
+
+            if order.is_expired(Clock::get()?.unix_timestamp) {
+                msg!("Skipping expired order {}", order_info.key());
+                continue;
+            }
+
+            // Callbacks need their own accounts so these orders must go through fulfill_order
+            if order.has_callback == 1 {
+                msg!("Skipping order with a callback {}", order_info.key());
+                continue;
+            }
+
+            // The worker may resubmit after a restart since it discovers orders from the queue
+            if order.submissions().iter().any(|s| s.oracle == oracle) {
+                msg!("Skipping already submitted order {}", order_info.key());
+                continue;
+            }
+
+            // A price the program rejects for one order shouldn't fail the rest of the batch
+            let submission = order_params.to_submission(oracle);
+            let now = Clock::get()?.unix_timestamp;
+            if let Err(error) = order.validate_submission(&market, &submission, now) {
+                msg!("Skipping order {}: {}", order_info.key(), error);
+                continue;
+            }
+
+            let order_queue = AccountLoader::<OrderQueueAccount>::try_from(order_queue_info)?;
+            let price_history = AccountLoader::<PriceHistoryAccount>::try_from(price_history_info)?;
+            order.submit_price(
+                order_info.key(),
+                &mut market,
+                &mut *order_queue.load_mut()?,
+                &mut *price_history.load_mut()?,
+                submission,
+                &event_authority
+            )?;
+            ctx.accounts.oracle.stats.record(&order, &Clock::get()?);
+            ctx.accounts.stake.lock_for_submission(
+                &order,
+                ctx.accounts.program_config.dispute_window
+            );
+
+            let fee = order.take_fee_share();
+            pay_oracle_fee(order_info, &mut ctx.accounts.oracle, fee)?;
+
+            if order.open_order == 0 && order.rate_limited == 1 {
+                release_rate_limit(rate_limit_info, &order)?;
+            }
+
+            // A later entry in the batch may be for the same market so persist its stats now
+            market.exit(ctx.program_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the latest recorded price at or before a timestamp from a market's price history. The
+    /// entry is set as the return data so other programs can read it through CPI.
+    pub fn get_historical_price(
+        ctx: Context<GetHistoricalPrice>,
+        params: GetHistoricalPriceParams
+    ) -> Result<PriceHistoryEntry> {
+        ctx.accounts.price_history
+            .load()?
+            .find(params.timestamp, params.max_age)
+            .ok_or(error!(ProgramError::PriceHistoryNotFound))
+    }
+
+    /// Close a fulfilled order and refund the rent to the payer once the dispute window has
+    /// elapsed. Anyone other than the order authority must also wait for the grace period.
+    pub fn close_order(ctx: Context<CloseOrder>) -> Result<()> {
+        let order = ctx.accounts.order.load()?;
+        let now = Clock::get()?.unix_timestamp;
+
+        if order.dispute.status() == DisputeStatus::Disputed {
+            return Err(error!(ProgramError::OrderDisputed));
+        }
+
+        // Closing deletes the submissions a dispute is filed against, orders settled from Pyth
+        // have none and can't be disputed
+        let dispute_window = if order.num_submissions == 0 {
+            0
+        } else {
+            ctx.accounts.program.dispute_window
+        };
+        if now < order.close_timestamp.saturating_add(dispute_window) {
+            return Err(error!(ProgramError::DisputeWindowActive));
+        }
+
+        if ctx.accounts.closer.key() != order.authority {
+            let closable_after = order.close_timestamp.saturating_add(
+                ctx.accounts.program.order_close_grace_period
+            );
+            if now < closable_after {
+                return Err(error!(ProgramError::OrderGracePeriodActive));
+            }
+        }
+
+        emit!(OrderClosedEvent {
             order: ctx.accounts.order.key(),
-            market: params.market,
-            timestamp: ctx.accounts.order.open_timestamp,
-            slot: ctx.accounts.order.open_slot,
+            market: order.market_name,
+            closer: ctx.accounts.closer.key(),
+            payer: order.payer,
         });
 
         Ok(())
     }
 
-    /// Fulfill an order with a backfilled price
-    pub fn fulfill_order(ctx: Context<FulfillOrder>, params: FulfillOrderParams) -> Result<()> {
-        ctx.accounts.order.open_order = 0;
-        ctx.accounts.order.close_timestamp = Clock::get()?.unix_timestamp;
-        ctx.accounts.order.close_slot = Clock::get()?.slot;
-        ctx.accounts.order.oracle_price = params.price;
+    /// Cancel an expired order that was never fulfilled and refund the rent to the payer
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        let order = ctx.accounts.order.load()?;
+
+        let now = Clock::get()?.unix_timestamp;
+        if !order.is_expired(now) {
+            return Err(error!(ProgramError::OrderNotExpired));
+        }
+
+        ctx.accounts.order_queue.load_mut()?.remove(&ctx.accounts.order.key(), now);
+        if order.rate_limited == 1 {
+            release_rate_limit(&ctx.accounts.rate_limit, &order)?;
+        }
 
-        emit!(OraclePriceFulfilledEvent {
-            market: params.market,
+        emit!(OrderCancelledEvent {
             order: ctx.accounts.order.key(),
+            market: order.market_name,
+            authority: order.authority,
+            open_timestamp: order.open_timestamp,
+            expiry_timestamp: order.expiry_timestamp,
+            num_submissions: order.num_submissions,
+        });
+
+        Ok(())
+    }
+
+    /// Rewrite an order created with the legacy Borsh layout in the zero-copy layout. The payer
+    /// tops up the rent for the larger account.
+    pub fn migrate_order(ctx: Context<MigrateOrder>) -> Result<()> {
+        let order_info = ctx.accounts.order.to_account_info();
+
+        let legacy = {
+            let data = order_info.try_borrow_data()?;
+            if data.len() <= ORDER_VERSION_OFFSET || data[..8] != OrderAccount::discriminator() {
+                return Err(error!(ErrorCode::AccountDiscriminatorMismatch));
+            }
+            if data[ORDER_VERSION_OFFSET] != 0 {
+                return Err(error!(ProgramError::OrderAlreadyMigrated));
+            }
+
+            OrderAccountV0::deserialize(&mut &data[8..])?
+        };
+
+        grow_legacy_account(
+            &order_info,
+            8 + std::mem::size_of::<OrderAccount>(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program
+        )?;
+
+        let mut data = order_info.try_borrow_mut_data()?;
+        data[8..].fill(0);
+        bytemuck::from_bytes_mut::<OrderAccount>(&mut data[8..]).migrate(legacy);
+
+        Ok(())
+    }
+
+    /// Rewrite the program account created by the originally deployed program in the current
+    /// layout, configured like initialize. Only its authority can migrate it. The legacy oracle
+    /// isn't carried over since it has to stake before it's added back with add_oracle.
+    pub fn migrate_program(ctx: Context<MigrateProgram>, params: InitializeParams) -> Result<()> {
+        let program_info = ctx.accounts.program.to_account_info();
+
+        let legacy = read_legacy_account::<ProgramAccount, ProgramAccountV0>(
+            &program_info.try_borrow_data()?,
+            8 + ProgramAccountV0::INIT_SPACE
+        )?;
+        if legacy.authority != ctx.accounts.authority.key() {
+            return Err(error!(ErrorCode::ConstraintHasOne));
+        }
+
+        let mut program = ProgramAccount {
+            bump: legacy.bump,
+            authority: legacy.authority,
+            markets: legacy.markets,
+            ..Default::default()
+        };
+        program.configure(&params)?;
+
+        grow_legacy_account(
+            &program_info,
+            8 + ProgramAccount::INIT_SPACE,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program
+        )?;
+
+        let mut data = program_info.try_borrow_mut_data()?;
+        program.try_serialize(&mut &mut data[..])
+    }
+
+    /// Rewrite a market account created by the originally deployed program in the current
+    /// layout, configured like add_market, and create its order queue and price history. The
+    /// program account has to be migrated first.
+    pub fn migrate_market(ctx: Context<MigrateMarket>, params: AddMarketParams) -> Result<()> {
+        let market_info = ctx.accounts.market.to_account_info();
+
+        let legacy = read_legacy_account::<MarketAccount, MarketAccountV0>(
+            &market_info.try_borrow_data()?,
+            8 + MarketAccountV0::INIT_SPACE
+        )?;
+
+        let mut market = MarketAccount {
+            bump: legacy.bump,
+            order_queue: ctx.accounts.order_queue.key(),
+            price_history: ctx.accounts.price_history.key(),
+            ..Default::default()
+        };
+        market.configure(&params)?;
+
+        grow_legacy_account(
+            &market_info,
+            8 + MarketAccount::INIT_SPACE,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program
+        )?;
+
+        market.try_serialize(&mut &mut market_info.try_borrow_mut_data()?[..])?;
+
+        let mut order_queue = ctx.accounts.order_queue.load_init()?;
+        order_queue.market = market_info.key();
+
+        let mut price_history = ctx.accounts.price_history.load_init()?;
+        price_history.market = market_info.key();
+
+        Ok(())
+    }
 
-            open_timestamp: ctx.accounts.order.open_timestamp,
-            open_slot: ctx.accounts.order.open_slot,
+    /// Rewrite an oracle account created by the originally deployed program in the current
+    /// layout. Legacy oracles never expired so the migrated oracle has to re-attest with
+    /// heartbeat or register_oracle before it can submit prices.
+    pub fn migrate_oracle(ctx: Context<MigrateOracle>) -> Result<()> {
+        let oracle_info = ctx.accounts.oracle.to_account_info();
 
-            latency_seconds: ctx.accounts.order.close_timestamp - ctx.accounts.order.open_timestamp,
-            latency_slots: ctx.accounts.order.close_slot - ctx.accounts.order.open_slot,
+        let legacy = read_legacy_account::<OracleAccount, OracleAccountV0>(
+            &oracle_info.try_borrow_data()?,
+            8 + OracleAccountV0::INIT_SPACE
+        )?;
 
+        let oracle = OracleAccount {
+            bump: legacy.bump,
+            authority: legacy.authority,
+            enclave_signer: legacy.enclave_signer,
+            verification_timestamp: legacy.verification_timestamp,
+            verification_slot: legacy.verification_slot,
+            valid_until_slot: 0,
+            unclaimed_fees: 0,
+            stats: FulfillmentStats::default(),
+        };
+
+        grow_legacy_account(
+            &oracle_info,
+            8 + OracleAccount::INIT_SPACE,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program
+        )?;
+
+        let mut data = oracle_info.try_borrow_mut_data()?;
+        oracle.try_serialize(&mut &mut data[..])
+    }
+
+    /// Withdraw the fees an oracle has earned from fulfilling orders
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>) -> Result<()> {
+        let amount = ctx.accounts.oracle.unclaimed_fees;
+        if amount == 0 {
+            return Err(error!(ProgramError::NoFeesToWithdraw));
+        }
+
+        ctx.accounts.oracle.sub_lamports(amount)?;
+        ctx.accounts.destination.add_lamports(amount)?;
+        ctx.accounts.oracle.unclaimed_fees = 0;
+
+        emit!(FeesWithdrawnEvent {
+            oracle: ctx.accounts.oracle.key(),
+            destination: ctx.accounts.destination.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Dispute an oracle's submission on a fulfilled order with a counter-price. Must be called
+    /// by a registered challenger within the dispute window.
+    pub fn dispute_order(ctx: Context<DisputeOrder>, params: DisputeOrderParams) -> Result<()> {
+        let clock = Clock::get()?;
+        let mut order = ctx.accounts.order.load_mut()?;
+
+        let dispute_deadline = order.close_timestamp.saturating_add(
+            ctx.accounts.program.dispute_window
+        );
+        if clock.unix_timestamp > dispute_deadline {
+            return Err(error!(ProgramError::DisputeWindowElapsed));
+        }
+
+        let oracle = ctx.accounts.stake.oracle;
+        if !order.submissions().iter().any(|s| s.oracle == oracle) {
+            return Err(error!(ProgramError::OracleSubmissionNotFound));
+        }
+
+        order.dispute = OrderDispute {
+            status: DisputeStatus::Disputed as u8,
+            challenger: ctx.accounts.challenger.key(),
+            oracle,
+            price: params.price,
+            publish_time: params.publish_time,
+            evidence: params.evidence,
+            timestamp: clock.unix_timestamp,
+            slashed_amount: 0,
+            ..Default::default()
+        };
+
+        ctx.accounts.stake.pending_disputes += 1;
+
+        emit!(OrderDisputedEvent {
+            order: ctx.accounts.order.key(),
+            market: order.market_name,
+            challenger: ctx.accounts.challenger.key(),
+            oracle,
+            oracle_price: order.oracle_price,
             price: params.price,
-            decimals: ctx.accounts.market.decimals,
+            publish_time: params.publish_time,
+            evidence: params.evidence,
+        });
+
+        Ok(())
+    }
+
+    /// Resolve a dispute as the program authority. An upheld dispute slashes up to slash_amount
+    /// from the oracle's stake and pays it to the challenger.
+    pub fn resolve_dispute(
+        ctx: Context<ResolveDispute>,
+        params: ResolveDisputeParams
+    ) -> Result<()> {
+        let mut order = ctx.accounts.order.load_mut()?;
+        let stake = &mut ctx.accounts.stake;
+
+        let slashed_amount = if params.upheld {
+            std::cmp::min(params.slash_amount, stake.amount)
+        } else {
+            0
+        };
+
+        if slashed_amount > 0 {
+            stake.sub_lamports(slashed_amount)?;
+            ctx.accounts.challenger.add_lamports(slashed_amount)?;
+            stake.amount -= slashed_amount;
+        }
+        stake.pending_disputes -= 1;
+
+        order.dispute.status = (if params.upheld {
+            DisputeStatus::Upheld
+        } else {
+            DisputeStatus::Rejected
+        }) as u8;
+        order.dispute.slashed_amount = slashed_amount;
+
+        emit!(DisputeResolvedEvent {
+            order: ctx.accounts.order.key(),
+            market: order.market_name,
+            challenger: order.dispute.challenger,
+            oracle: order.dispute.oracle,
+            upheld: params.upheld,
+            slashed_amount,
         });
 
         Ok(())
     }
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct ProgramAccount {
-    pub bump: u8,
-    pub authority: Pubkey,
-    pub oracle: Pubkey,
-    #[max_len(16)]
-    pub markets: Vec<[u8; 8]>,
+/// Read an account in the layout the originally deployed program wrote. Its Borsh accounts
+/// weren't versioned so a legacy account is recognized by its size.
+fn read_legacy_account<T: Discriminator, L: AnchorDeserialize>(
+    data: &[u8],
+    legacy_space: usize
+) -> Result<L> {
+    if data.len() < 8 || data[..8] != T::discriminator() {
+        return Err(error!(ErrorCode::AccountDiscriminatorMismatch));
+    }
+    if data.len() != legacy_space {
+        return Err(error!(ProgramError::AccountAlreadyMigrated));
+    }
+
+    Ok(L::deserialize(&mut &data[8..])?)
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct MarketAccount {
-    pub bump: u8,
-    pub name: [u8; 8],
+/// Grow a legacy account to the size of its current layout, with the payer topping up the rent
+fn grow_legacy_account<'info>(
+    info: &AccountInfo<'info>,
+    space: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>
+) -> Result<()> {
+    let top_up = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(system_program.to_account_info(), system_program::Transfer {
+                from: payer.to_account_info(),
+                to: info.clone(),
+            }),
+            top_up
+        )?;
+    }
 
-    // market configs
-    pub decimals: u32,
-    pub oracle_staleness_threshold: u32,
+    info.realloc(space, false)?;
 
-    // we could store & pop open orders here per market
+    Ok(())
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct OracleAccount {
-    pub bump: u8,
-    pub authority: Pubkey,
-    pub enclave_signer: Pubkey,
-    pub verification_timestamp: i64,
-    pub verification_slot: u64,
-    pub valid_until_slot: u64,
+/// Escrow the market's fee and deposit on a new order that needs a backfilled price. This has to
+/// happen before the order is loaded since the transfer can't run while the order's data is
+/// borrowed.
+fn escrow_order_fee<'info>(
+    order: &AccountLoader<'info, OrderAccount>,
+    market: &MarketAccount,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>
+) -> Result<()> {
+    // The fee is paid out to the oracles as they submit prices, the deposit stays on the order
+    // until it's closed or cancelled
+    let amount = market.order_fee
+        .checked_add(market.order_deposit)
+        .ok_or(error!(ProgramError::InvalidFee))?;
+    if amount == 0 {
+        return Ok(());
+    }
+
+    system_program::transfer(
+        CpiContext::new(system_program.to_account_info(), system_program::Transfer {
+            from: payer.to_account_info(),
+            to: order.to_account_info(),
+        }),
+        amount
+    )
 }
 
+/// Record the escrowed fee on a new order, count it against its authority's open order limit,
+/// push it onto the market's order queue and ask the oracles for a price
+fn request_oracle_price(
+    order_key: Pubkey,
+    order: &mut OrderAccount,
+    market: &MarketAccount,
+    rate_limit: &mut RateLimitAccount,
+    order_queue: &mut OrderQueueAccount,
+    clock: &Clock,
+    event_authority: &EventAuthority
+) -> Result<()> {
+    order.fee = market.order_fee;
+
+    rate_limit.add_order(market.max_open_orders)?;
+    order.rate_limited = 1;
+
+    order_queue.push(
+        QueuedOrder {
+            order: order_key,
+            open_timestamp: order.open_timestamp,
+            expiry_timestamp: order.expiry_timestamp,
+        },
+        clock.unix_timestamp
+    )?;
+
+    event_authority.emit(
+        &(OraclePriceRequestedEvent {
+            quorum: order.quorum,
+            order: order_key,
+            authority: order.authority,
+            market: market.name,
+            timestamp: order.requested_timestamp,
+            slot: order.open_slot,
+            expiry_timestamp: order.expiry_timestamp,
+            twap_window: order.twap_window,
+            candle_end: order.candle_end,
+            fee: order.fee,
+            callback: order.callback(),
+        })
+    )?;
+
+    Ok(())
+}
+
+/// Release a settled or cancelled order from its authority's RateLimitAccount. The account is
+/// passed unchecked since orders created before rate limiting aren't counted in one.
+fn release_rate_limit(info: &AccountInfo, order: &OrderAccount) -> Result<()> {
+    if info.owner != &crate::ID {
+        return Err(error!(ErrorCode::AccountOwnedByWrongProgram));
+    }
+
+    let mut data = info.try_borrow_mut_data()?;
+    let mut rate_limit = RateLimitAccount::try_deserialize(&mut &data[..])?;
+    if rate_limit.market != order.market || rate_limit.authority != order.authority {
+        return Err(error!(ProgramError::InvalidRateLimit));
+    }
+
+    rate_limit.remove_order();
+    rate_limit.try_serialize(&mut &mut data[..])
+}
+
+/// CPI into the order's callback program with the fulfilled price. The instruction is signed by
+/// the callback authority PDA seeded by the callback program's id, which no other program can get
+/// signed, so a signer forwarded through a nested CPI can't vouch for forged data.
+fn invoke_callback<'info>(
+    order_loader: &AccountLoader<'info, OrderAccount>,
+    market: &MarketAccount,
+    remaining_accounts: &[AccountInfo<'info>]
+) -> Result<()> {
+    let order = order_loader.load()?;
+    let callback = order.callback().unwrap();
+
+    let (callback_authority, callback_authority_bump) = Pubkey::find_program_address(
+        &[CALLBACK_AUTHORITY_SEED, callback.program_id.as_ref()],
+        &crate::ID
+    );
+    if
+        remaining_accounts.len() != callback.accounts.len() + 2 ||
+        remaining_accounts[0].key() != callback.program_id ||
+        remaining_accounts[1].key() != callback_authority ||
+        remaining_accounts[2..]
+            .iter()
+            .zip(callback.accounts.iter())
+            .any(|(info, account)| info.key() != account.pubkey)
+    {
+        return Err(error!(ProgramError::InvalidCallbackAccounts));
+    }
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(callback_authority, true),
+        AccountMeta::new_readonly(order_loader.key(), false)
+    ];
+    accounts.extend(
+        callback.accounts.iter().map(|account| {
+            if account.is_writable {
+                AccountMeta::new(account.pubkey, false)
+            } else {
+                AccountMeta::new_readonly(account.pubkey, false)
+            }
+        })
+    );
+
+    let mut data = callback.discriminator.to_vec();
+    (FulfilledPrice {
+        order: order_loader.key(),
+        market: market.name,
+        price: order.oracle_price,
+        decimals: market.decimals,
+        publish_time: order.price_publish_time,
+        confidence: order.price_confidence,
+        open_timestamp: order.open_timestamp,
+        requested_timestamp: order.requested_timestamp,
+        num_samples: order.num_samples,
+        fill_status: order.fill_status(),
+        candle: order.candle(),
+    }).serialize(&mut data)?;
+
+    // The order's data can't be borrowed while it's passed to the callback program
+    drop(order);
+
+    let mut account_infos = vec![order_loader.to_account_info()];
+    account_infos.extend_from_slice(remaining_accounts);
+
+    invoke_signed(
+        &(Instruction {
+            program_id: callback.program_id,
+            accounts,
+            data,
+        }),
+        &account_infos,
+        &[&[CALLBACK_AUTHORITY_SEED, callback.program_id.as_ref(), &[callback_authority_bump]]]
+    )?;
+
+    Ok(())
+}
+
+/// Move an oracle's share of an order's escrowed fee onto its oracle account
+fn pay_oracle_fee<'info>(
+    order: &AccountInfo<'info>,
+    oracle: &mut Account<'info, OracleAccount>,
+    fee: u64
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+
+    order.sub_lamports(fee)?;
+    oracle.add_lamports(fee)?;
+    oracle.unclaimed_fees = oracle.unclaimed_fees
+        .checked_add(fee)
+        .ok_or(error!(ProgramError::InvalidFee))?;
+
+    Ok(())
+}
+
+/// The event authority `#[event_cpi]` adds to an instruction's accounts, for emitting events the
+/// way emit_cpi! does from helpers that don't have the instruction's Context. Indexers read these
+/// events from the transaction's inner instructions, which unlike the program logs can't be
+/// truncated.
+pub struct EventAuthority<'info> {
+    pub info: AccountInfo<'info>,
+    pub bump: u8,
+}
+
+impl<'info> EventAuthority<'info> {
+    pub fn new(info: &AccountInfo<'info>, bump: u8) -> Self {
+        Self {
+            info: info.clone(),
+            bump,
+        }
+    }
+
+    /// Emit the event as the data of a self-CPI signed by the event authority
+    pub fn emit<E: anchor_lang::Event>(&self, event: &E) -> Result<()> {
+        let data: Vec<u8> = anchor_lang::event::EVENT_IX_TAG_LE
+            .into_iter()
+            .chain(event.data())
+            .collect();
+
+        invoke_signed(
+            &Instruction::new_with_bytes(
+                crate::ID,
+                &data,
+                vec![AccountMeta::new_readonly(self.info.key(), true)]
+            ),
+            std::slice::from_ref(&self.info),
+            &[&[EVENT_AUTHORITY_SEED, &[self.bump]]]
+        )?;
+
+        Ok(())
+    }
+}
+
+pub const MAX_MARKETS: usize = 16;
+pub const MAX_ORACLES: usize = 8;
+pub const MAX_CALLBACK_ACCOUNTS: usize = 8;
+pub const MAX_CHALLENGERS: usize = 8;
+/// Longest TWAP window in seconds, the oracles fetch one price per second of the window
+pub const MAX_TWAP_WINDOW: u32 = 3600;
+/// Longest interval in seconds a candle order can aggregate over
+pub const MAX_CANDLE_INTERVAL: i64 = 3600;
+/// Seed of the event authority, the same as Anchor's event-cpi so its dispatch accepts our events
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+/// Seed of the PDA that signs an order's callback, along with the callback program's id
+pub const CALLBACK_AUTHORITY_SEED: &[u8] = b"CALLBACK";
+
+/// Bits for the price providers that contributed to a submitted price
+pub const SOURCE_PYTH: u8 = 1 << 0;
+pub const SOURCE_COINBASE: u8 = 1 << 1;
+
+/// Each new latency moves the exponentially weighted average 1/LATENCY_EWMA_WEIGHT of the way
+pub const LATENCY_EWMA_WEIGHT: u64 = 8;
+/// The exponentially weighted averages are stored scaled up by this to keep their precision
+pub const LATENCY_EWMA_SCALE: u64 = 1_000;
+
+#[account]
+#[derive(Default, InitSpace)]
+pub struct ProgramAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub attestation_validity_slots: u64,
+    pub oracle_quorum: u8,
+    pub order_close_grace_period: i64,
+    /// Seconds after an order is fulfilled that a challenger can dispute it
+    pub dispute_window: i64,
+    /// Lamports an oracle must have staked to be added and to submit prices, so every submission
+    /// can be disputed
+    pub min_stake: u64,
+    #[max_len(MAX_ORACLES)]
+    pub oracles: Vec<Pubkey>,
+    #[max_len(MAX_CHALLENGERS)]
+    pub challengers: Vec<Pubkey>,
+    #[max_len(MAX_MARKETS)]
+    pub markets: Vec<[u8; 8]>,
+}
+
+impl ProgramAccount {
+    /// Apply the configs a new or migrated program account is created with
+    pub fn configure(&mut self, params: &InitializeParams) -> Result<()> {
+        if params.attestation_validity_slots == 0 {
+            return Err(error!(ProgramError::InvalidAttestationWindow));
+        }
+
+        if params.oracle_quorum == 0 || (params.oracle_quorum as usize) > MAX_ORACLES {
+            return Err(error!(ProgramError::InvalidOracleQuorum));
+        }
+
+        if params.min_stake == 0 {
+            return Err(error!(ProgramError::InvalidStakeAmount));
+        }
+
+        self.attestation_validity_slots = params.attestation_validity_slots;
+        self.oracle_quorum = params.oracle_quorum;
+        self.order_close_grace_period = params.order_close_grace_period;
+        self.dispute_window = params.dispute_window;
+        self.min_stake = params.min_stake;
+
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default, InitSpace)]
+pub struct MarketAccount {
+    pub bump: u8,
+    pub name: [u8; 8],
+
+    // market configs
+    pub decimals: u32,
+    pub oracle_staleness_threshold: u32,
+    pub pyth_price_feed: Pubkey,
+    /// Seconds an order can wait for a backfilled price before it expires
+    pub order_ttl: u32,
+    /// Maximum seconds between a submitted price's publish time and the order's open time
+    pub max_price_time_deviation: u32,
+    /// Paused markets reject new orders
+    pub paused: bool,
+    /// The ring buffer of this market's open orders
+    pub order_queue: Pubkey,
+    /// Lamports escrowed by every order that needs a backfilled price, paid out to the oracles
+    pub order_fee: u64,
+    /// Furthest back in seconds an order can request a price for, 0 disables historical orders
+    pub max_lookback: u32,
+    /// The ring buffer of this market's recently fulfilled prices
+    pub price_history: Pubkey,
+    /// Latency of the orders the oracles have settled in this market
+    pub stats: FulfillmentStats,
+    /// Most orders an authority can have waiting on the oracles in this market, 0 for no limit
+    pub max_open_orders: u32,
+    /// Lamports escrowed by every order that needs a backfilled price on top of the fee. It's
+    /// refunded to the payer with the rent when the order is closed or cancelled.
+    pub order_deposit: u64,
+    /// Basis points a submitted price can move away from the last fulfilled price for each second
+    /// between their publish times, 0 disables the check
+    pub max_deviation_bps_per_second: u32,
+    /// The last point price the oracles settled in this market and its publish time
+    pub last_price: u64,
+    pub last_price_timestamp: i64,
+}
+
+impl MarketAccount {
+    /// Apply the configs a new or migrated market account is created with
+    pub fn configure(&mut self, params: &AddMarketParams) -> Result<()> {
+        if params.name == [0u8; 8] {
+            return Err(error!(ProgramError::InvalidMarketName));
+        }
+
+        if params.order_ttl == 0 {
+            return Err(error!(ProgramError::InvalidOrderTtl));
+        }
+
+        self.name = params.name;
+        self.decimals = params.decimals;
+        self.oracle_staleness_threshold = params.oracle_staleness_threshold;
+        self.pyth_price_feed = params.pyth_price_feed;
+        self.order_ttl = params.order_ttl;
+        self.max_price_time_deviation = params.max_price_time_deviation;
+        self.order_fee = params.order_fee;
+        self.max_lookback = params.max_lookback;
+        self.max_open_orders = params.max_open_orders;
+        self.order_deposit = params.order_deposit;
+        self.max_deviation_bps_per_second = params.max_deviation_bps_per_second;
+
+        Ok(())
+    }
+
+    /// Check that a submitted price is within max_deviation_bps_per_second of the last fulfilled
+    /// price. The bound widens with the seconds between their publish times, at least one.
+    pub fn check_price_deviation(&self, price: u64, publish_time: i64) -> Result<()> {
+        if self.max_deviation_bps_per_second == 0 || self.last_price == 0 {
+            return Ok(());
+        }
+
+        let seconds = publish_time.abs_diff(self.last_price_timestamp).max(1);
+        let max_deviation = (self.last_price as u128)
+            .saturating_mul(self.max_deviation_bps_per_second.into())
+            .saturating_mul(seconds.into()) / 10_000;
+        if (price.abs_diff(self.last_price) as u128) > max_deviation {
+            return Err(error!(ProgramError::PriceDeviationExceeded));
+        }
+
+        Ok(())
+    }
+
+    /// Record a fulfilled price as the reference for the deviation check, unless it's older than
+    /// the current one like a historical order's price
+    pub fn record_price(&mut self, price: u64, publish_time: i64) {
+        if publish_time >= self.last_price_timestamp {
+            self.last_price = price;
+            self.last_price_timestamp = publish_time;
+        }
+    }
+
+    /// Check that a requested timestamp isn't in the future or further back than the lookback
+    pub fn check_lookback(&self, timestamp: i64, now: i64) -> Result<()> {
+        if timestamp > now || now - timestamp > self.max_lookback.into() {
+            return Err(error!(ProgramError::InvalidOrderTimestamp));
+        }
+
+        Ok(())
+    }
+
+    /// Convert a Pyth price or confidence mantissa and exponent to the market's fixed decimals
+    pub fn scale_price(&self, price: u64, expo: i32) -> Result<u64> {
+        let scale = (self.decimals as i32) + expo;
+        let factor = (10u64)
+            .checked_pow(scale.unsigned_abs())
+            .ok_or(error!(ProgramError::InvalidPrice))?;
+
+        if scale >= 0 {
+            price.checked_mul(factor).ok_or(error!(ProgramError::InvalidPrice))
+        } else {
+            Ok(price / factor)
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct OracleAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub enclave_signer: Pubkey,
+    pub verification_timestamp: i64,
+    pub verification_slot: u64,
+    pub valid_until_slot: u64,
+    /// Lamports earned from order fees that the authority can withdraw
+    pub unclaimed_fees: u64,
+    /// Latency of the prices this oracle has submitted
+    pub stats: FulfillmentStats,
+}
+
+impl OracleAccount {
+    /// Record a fresh attestation valid for the given number of slots
+    pub fn attest(&mut self, validity_slots: u64) -> Result<()> {
+        let clock = Clock::get()?;
+
+        self.verification_timestamp = clock.unix_timestamp;
+        self.verification_slot = clock.slot;
+        self.valid_until_slot = clock.slot.saturating_add(validity_slots);
+
+        Ok(())
+    }
+
+    pub fn is_expired(&self, slot: u64) -> bool {
+        slot > self.valid_until_slot
+    }
+}
+
+/// Running latency statistics measured from when an order opened, for an oracle's accepted
+/// submissions or a market's settled orders
+#[derive(Debug, Default, Clone, Copy, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct FulfillmentStats {
+    pub fulfilled_count: u64,
+    pub total_latency_slots: u64,
+    pub total_latency_seconds: u64,
+    /// Exponentially weighted average latencies, scaled up by LATENCY_EWMA_SCALE
+    pub ewma_latency_slots: u64,
+    pub ewma_latency_seconds: u64,
+    pub max_latency_slots: u64,
+    pub max_latency_seconds: u64,
+    pub last_fulfillment_slot: u64,
+}
+
+impl FulfillmentStats {
+    /// Record the latency of an order fulfilled at the current clock
+    pub fn record(&mut self, order: &OrderAccount, clock: &Clock) {
+        let latency_slots = clock.slot.saturating_sub(order.open_slot);
+        let latency_seconds = (clock.unix_timestamp - order.open_timestamp).max(0) as u64;
+
+        self.ewma_latency_slots = Self::ewma(
+            self.ewma_latency_slots,
+            latency_slots,
+            self.fulfilled_count
+        );
+        self.ewma_latency_seconds = Self::ewma(
+            self.ewma_latency_seconds,
+            latency_seconds,
+            self.fulfilled_count
+        );
+
+        self.fulfilled_count = self.fulfilled_count.saturating_add(1);
+        self.total_latency_slots = self.total_latency_slots.saturating_add(latency_slots);
+        self.total_latency_seconds = self.total_latency_seconds.saturating_add(latency_seconds);
+        self.max_latency_slots = std::cmp::max(self.max_latency_slots, latency_slots);
+        self.max_latency_seconds = std::cmp::max(self.max_latency_seconds, latency_seconds);
+        self.last_fulfillment_slot = clock.slot;
+    }
+
+    /// Fold a latency into a scaled average, the first latency seeds it
+    fn ewma(average: u64, latency: u64, count: u64) -> u64 {
+        let scaled = latency.saturating_mul(LATENCY_EWMA_SCALE);
+        if count == 0 {
+            return scaled;
+        }
+
+        if scaled >= average {
+            average + (scaled - average) / LATENCY_EWMA_WEIGHT
+        } else {
+            average - (average - scaled) / LATENCY_EWMA_WEIGHT
+        }
+    }
+}
+
+/// Lamports an oracle has put at stake for the prices it submits
+#[account]
+#[derive(InitSpace)]
+pub struct StakeAccount {
+    pub bump: u8,
+    pub oracle: Pubkey,
+    pub amount: u64,
+    /// Number of unresolved disputes against the oracle, stake can't be withdrawn while non-zero
+    pub pending_disputes: u32,
+    /// Stake can't be withdrawn until this timestamp has passed, when the dispute window of the
+    /// oracle's last submission has elapsed
+    pub locked_until: i64,
+}
+
+impl StakeAccount {
+    /// Lock the stake until a submission on the order can no longer be disputed. The order
+    /// settles before it expires at the latest, so its dispute window ends by then.
+    pub fn lock_for_submission(&mut self, order: &OrderAccount, dispute_window: i64) {
+        let deadline = order.expiry_timestamp.saturating_add(dispute_window);
+        self.locked_until = std::cmp::max(self.locked_until, deadline);
+    }
+}
+
+pub const ORDER_QUEUE_CAPACITY: usize = 128;
+
+/// A ring buffer of a market's open orders so the worker can discover pending work with a single
+/// account read instead of a program account scan
+#[account(zero_copy)]
+pub struct OrderQueueAccount {
+    pub market: Pubkey,
+    pub head: u32,
+    pub len: u32,
+    pub orders: [QueuedOrder; ORDER_QUEUE_CAPACITY],
+}
+
+impl OrderQueueAccount {
+    pub fn push(&mut self, order: QueuedOrder, now: i64) -> Result<()> {
+        self.compact(now);
+
+        if (self.len as usize) >= ORDER_QUEUE_CAPACITY {
+            return Err(error!(ProgramError::OrderQueueFull));
+        }
+
+        let idx = ((self.head + self.len) as usize) % ORDER_QUEUE_CAPACITY;
+        self.orders[idx] = order;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Remove a settled or cancelled order and reclaim its slot
+    pub fn remove(&mut self, order: &Pubkey, now: i64) {
+        for i in 0..self.len {
+            let idx = ((self.head + i) as usize) % ORDER_QUEUE_CAPACITY;
+            if self.orders[idx].order == *order {
+                self.orders[idx] = QueuedOrder::default();
+                break;
+            }
+        }
+
+        self.compact(now);
+    }
+
+    /// The queued orders that are still waiting for a price, oldest first
+    pub fn open_orders(&self, now: i64) -> Vec<QueuedOrder> {
+        (0..self.len)
+            .map(|i| self.orders[((self.head + i) as usize) % ORDER_QUEUE_CAPACITY])
+            .filter(|o| o.order != Pubkey::default() && o.expiry_timestamp >= now)
+            .collect()
+    }
+
+    /// Drop removed and expired orders from anywhere in the queue, shifting the remaining orders
+    /// toward the head so a long-lived order at the head doesn't hold the slots behind it
+    fn compact(&mut self, now: i64) {
+        let mut len = 0;
+        for i in 0..self.len {
+            let idx = ((self.head + i) as usize) % ORDER_QUEUE_CAPACITY;
+            let order = self.orders[idx];
+            self.orders[idx] = QueuedOrder::default();
+
+            if order.order != Pubkey::default() && order.expiry_timestamp >= now {
+                self.orders[((self.head + len) as usize) % ORDER_QUEUE_CAPACITY] = order;
+                len += 1;
+            }
+        }
+
+        self.len = len;
+    }
+}
+
+#[zero_copy]
+#[derive(Default, Debug)]
+pub struct QueuedOrder {
+    pub order: Pubkey,
+    pub open_timestamp: i64,
+    pub expiry_timestamp: i64,
+}
+
+pub const PRICE_HISTORY_CAPACITY: usize = 256;
+
+/// A ring buffer of a market's most recently fulfilled prices. Other programs can read it by
+/// deserializing the account or through the get_historical_price instruction.
+#[account(zero_copy)]
+pub struct PriceHistoryAccount {
+    pub market: Pubkey,
+    pub head: u32,
+    pub len: u32,
+    pub entries: [PriceHistoryEntry; PRICE_HISTORY_CAPACITY],
+}
+
+impl PriceHistoryAccount {
+    /// Append a price, overwriting the oldest entry once the buffer is full
+    pub fn push(&mut self, entry: PriceHistoryEntry) {
+        let idx = ((self.head + self.len) as usize) % PRICE_HISTORY_CAPACITY;
+        self.entries[idx] = entry;
+
+        if (self.len as usize) < PRICE_HISTORY_CAPACITY {
+            self.len += 1;
+        } else {
+            self.head = (self.head + 1) % (PRICE_HISTORY_CAPACITY as u32);
+        }
+    }
+
+    /// Rescale the recorded prices from 10^-expo to the market's decimals after they change
+    pub fn rescale(&mut self, market: &MarketAccount, expo: i32) -> Result<()> {
+        for entry in self.entries.iter_mut() {
+            entry.price = market.scale_price(entry.price, expo)?;
+            entry.confidence = market.scale_price(entry.confidence, expo)?;
+        }
+
+        Ok(())
+    }
+
+    /// The recorded prices in the order they were fulfilled, oldest first
+    pub fn history(&self) -> Vec<PriceHistoryEntry> {
+        (0..self.len)
+            .map(|i| self.entries[((self.head + i) as usize) % PRICE_HISTORY_CAPACITY])
+            .collect()
+    }
+
+    /// The latest price at or before the timestamp and no more than max_age seconds before it.
+    /// Historical orders can be fulfilled out of timestamp order so every entry is checked.
+    pub fn find(&self, timestamp: i64, max_age: u32) -> Option<PriceHistoryEntry> {
+        self.history()
+            .into_iter()
+            .filter(|e| e.timestamp <= timestamp && timestamp - e.timestamp <= max_age.into())
+            .max_by_key(|e| e.timestamp)
+    }
+}
+
+#[zero_copy]
+#[derive(Default, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct PriceHistoryEntry {
+    /// The time the price is for
+    pub timestamp: i64,
+    pub price: u64,
+    pub confidence: u64,
+}
+
+/// Tracks the next order nonce for an authority so order addresses can be derived
+#[account]
+#[derive(InitSpace)]
+pub struct UserAccount {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub nonce: u64,
+}
+
+impl UserAccount {
+    /// Take the nonce for a new order and advance it
+    pub fn next_nonce(&mut self) -> Result<u64> {
+        let nonce = self.nonce;
+        self.nonce = nonce.checked_add(1).ok_or(error!(ProgramError::NonceOverflow))?;
+
+        Ok(nonce)
+    }
+}
+
+/// Counts an authority's orders in a market that are waiting on the oracles so the market can
+/// cap them
 #[account]
 #[derive(InitSpace)]
+pub struct RateLimitAccount {
+    pub bump: u8,
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub open_orders: u32,
+}
+
+impl RateLimitAccount {
+    /// Count a new order, failing if the authority already has the market's limit open
+    pub fn add_order(&mut self, max_open_orders: u32) -> Result<()> {
+        if max_open_orders > 0 && self.open_orders >= max_open_orders {
+            return Err(error!(ProgramError::OpenOrderLimitReached));
+        }
+
+        self.open_orders += 1;
+        Ok(())
+    }
+
+    /// Release an order once it's settled or cancelled
+    pub fn remove_order(&mut self) {
+        self.open_orders = self.open_orders.saturating_sub(1);
+    }
+}
+
+/// Byte offsets of the order fields off-chain filters match on, including the 8-byte
+/// discriminator. Legacy orders have the same offsets so a filter matches either layout.
+pub const ORDER_OPEN_OFFSET: usize = 8;
+pub const ORDER_VERSION_OFFSET: usize = 9;
+pub const ORDER_AUTHORITY_OFFSET: usize = 40;
+pub const ORDER_MARKET_OFFSET: usize = 72;
+
+/// The current order layout. Orders created with the legacy Borsh layout have a 0 in its place
+/// and must be converted with migrate_order before they can be loaded.
+pub const ORDER_ACCOUNT_VERSION: u8 = 1;
+
+/// An order in a fixed-size layout so it can be loaded without deserializing and filtered by
+/// byte offset. Enums are stored as u8 tags and optional fields inline behind a flag, use the
+/// accessors to read them.
+#[account(zero_copy)]
 pub struct OrderAccount {
     // Flags for gPA filtering
     // 0 = false, 1 = true
     pub open_order: u8,
-    pub reserved: [u8; 31],
+    pub version: u8,
+    pub bump: u8,
+    pub quorum: u8,
+    pub num_sources: u8,
+    pub source_bitmap: u8,
+    pub num_submissions: u8,
+    /// The OrderKind, 0 = price, 1 = limit, 2 = stop
+    pub kind: u8,
+    /// The OrderSide of a limit or stop order
+    pub side: u8,
+    /// The FillStatus, whether a limit or stop order filled at the settled price
+    pub fill_status: u8,
+    pub has_candle: u8,
+    pub has_callback: u8,
+    /// Whether the order is counted in its authority's RateLimitAccount
+    pub rate_limited: u8,
+    pub reserved: [u8; 19],
     pub authority: Pubkey,
     pub market: Pubkey,
+    pub payer: Pubkey,
+    pub nonce: u64,
     pub market_name: [u8; 8],
     pub open_timestamp: i64,
     pub open_slot: u64,
+    /// The time the price is requested for, the open time unless the caller asked for a past
+    /// timestamp
+    pub requested_timestamp: i64,
+    pub expiry_timestamp: i64,
     pub close_timestamp: i64,
     pub close_slot: u64,
     pub oracle_price: u64,
+    pub price_publish_time: i64,
+    pub price_confidence: u64,
+    /// End of the interval a candle order aggregates over, 0 for other orders
+    pub candle_end: i64,
+    /// Lamports still escrowed for the oracles, any remainder is refunded when the order is closed
+    pub fee: u64,
+    /// Number of per-second prices averaged into the price, 1 for a point price
+    pub num_samples: u32,
+    /// Seconds after the open time that a TWAP order averages over, 0 for a point price
+    pub twap_window: u32,
+    /// The limit price of a limit order or the trigger price of a stop order
+    pub kind_price: u64,
+    /// The settled candle for a candle order
+    pub candle: Ohlc,
+    pub callback: OrderCallbackData,
+    pub dispute: OrderDispute,
+    pub submissions: [OracleSubmission; MAX_ORACLES],
+}
+
+impl OrderAccount {
+    /// Open a new order in the market at the current clock
+    pub fn open(
+        &mut self,
+        market: &Account<MarketAccount>,
+        quorum: u8,
+        requested_timestamp: i64,
+        twap_window: u32,
+        candle_end: i64,
+        clock: &Clock
+    ) {
+        self.open_order = 1;
+        self.version = ORDER_ACCOUNT_VERSION;
+        self.market = market.key();
+        self.market_name = market.name;
+        self.open_timestamp = clock.unix_timestamp;
+        self.open_slot = clock.slot;
+        self.requested_timestamp = requested_timestamp;
+        self.twap_window = twap_window;
+        self.candle_end = candle_end;
+        // TWAP and candle orders can't be fulfilled until their window closes so the TTL starts
+        // from there
+        self.expiry_timestamp = std::cmp::max(clock.unix_timestamp, self.price_timestamp())
+            .saturating_add(market.order_ttl.into());
+        self.quorum = quorum;
+    }
+
+    /// Whether an order account has the current layout. Legacy orders are shorter so they can't
+    /// be loaded until they're migrated.
+    pub fn is_current(info: &AccountInfo) -> Result<bool> {
+        let data = info.try_borrow_data()?;
+
+        Ok(
+            data.len() == 8 + std::mem::size_of::<Self>() &&
+                data[ORDER_VERSION_OFFSET] == ORDER_ACCOUNT_VERSION
+        )
+    }
+
+    /// Convert an order from the legacy layout. The authority becomes the payer refunded on close
+    /// since the legacy order didn't record one. Open legacy orders were never queued for the
+    /// oracles so they're expired and the authority can cancel them. The account must be zeroed.
+    pub fn migrate(&mut self, legacy: OrderAccountV0) {
+        self.open_order = legacy.open_order;
+        self.version = ORDER_ACCOUNT_VERSION;
+        self.set_kind(OrderKind::Price);
+        self.authority = legacy.authority;
+        self.market = legacy.market;
+        self.payer = legacy.authority;
+        self.market_name = legacy.market_name;
+        self.open_timestamp = legacy.open_timestamp;
+        self.open_slot = legacy.open_slot;
+        self.requested_timestamp = legacy.open_timestamp;
+        self.expiry_timestamp = legacy.open_timestamp;
+        self.close_timestamp = legacy.close_timestamp;
+        self.close_slot = legacy.close_slot;
+        self.oracle_price = legacy.oracle_price;
+        self.num_samples = 1;
+    }
+
+    pub fn kind(&self) -> OrderKind {
+        let side = if self.side == (OrderSide::Buy as u8) { OrderSide::Buy } else { OrderSide::Sell };
+
+        match self.kind {
+            1 => OrderKind::Limit { side, limit_price: self.kind_price },
+            2 => OrderKind::Stop { side, trigger_price: self.kind_price },
+            _ => OrderKind::Price,
+        }
+    }
+
+    pub fn set_kind(&mut self, kind: OrderKind) {
+        (self.kind, self.side, self.kind_price) = match kind {
+            OrderKind::Price => (0, 0, 0),
+            OrderKind::Limit { side, limit_price } => (1, side as u8, limit_price),
+            OrderKind::Stop { side, trigger_price } => (2, side as u8, trigger_price),
+        };
+    }
+
+    pub fn fill_status(&self) -> FillStatus {
+        self.fill_status.into()
+    }
+
+    pub fn candle(&self) -> Option<Ohlc> {
+        (self.has_candle == 1).then_some(self.candle)
+    }
+
+    pub fn set_candle(&mut self, candle: Option<Ohlc>) {
+        self.has_candle = candle.is_some().into();
+        self.candle = candle.unwrap_or_default();
+    }
+
+    pub fn callback(&self) -> Option<OrderCallback> {
+        (self.has_callback == 1).then(|| OrderCallback::from(&self.callback))
+    }
+
+    pub fn set_callback(&mut self, callback: Option<&OrderCallback>) {
+        self.has_callback = callback.is_some().into();
+        self.callback = callback.map(OrderCallbackData::from).unwrap_or_default();
+    }
+
+    /// The oracles' submissions so far, in the order they were submitted
+    pub fn submissions(&self) -> &[OracleSubmission] {
+        &self.submissions[..self.num_submissions as usize]
+    }
+
+    fn push_submission(&mut self, submission: OracleSubmission) {
+        self.submissions[self.num_submissions as usize] = submission;
+        self.num_submissions += 1;
+    }
+
+    /// The time the order's price is for. TWAP and candle orders are priced at the end of their
+    /// window.
+    pub fn price_timestamp(&self) -> i64 {
+        if self.candle_end > 0 {
+            return self.candle_end;
+        }
+
+        self.requested_timestamp.saturating_add(self.twap_window.into())
+    }
+
+    /// The most per-second prices a submission can be built from
+    pub fn max_samples(&self) -> u32 {
+        if self.candle_end > 0 {
+            return (self.candle_end - self.requested_timestamp + 1) as u32;
+        }
+
+        std::cmp::max(self.twap_window, 1)
+    }
+
+    /// Derive the order address for the authority's nonce in a market
+    pub fn find_address(market: &Pubkey, authority: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[market.to_bytes().as_ref(), authority.to_bytes().as_ref(), nonce.to_le_bytes().as_ref()],
+            &crate::ID
+        )
+    }
+
+    /// Check an oracle's price can be accepted for the order without changing any state, so
+    /// fulfill_orders can skip an order it fails for
+    pub fn validate_submission(
+        &self,
+        market: &MarketAccount,
+        submission: &OracleSubmission,
+        now: i64
+    ) -> Result<()> {
+        if self.is_expired(now) {
+            return Err(error!(ProgramError::OrderExpired));
+        }
+
+        if self.submissions().iter().any(|s| s.oracle == submission.oracle) {
+            return Err(error!(ProgramError::DuplicateOracleSubmission));
+        }
+
+        if now < self.price_timestamp() {
+            return Err(error!(ProgramError::PriceWindowNotElapsed));
+        }
+
+        if submission.num_samples == 0 || submission.num_samples > self.max_samples() {
+            return Err(error!(ProgramError::InvalidSampleCount));
+        }
+
+        // Candle orders need a candle that closes at the submitted price, other orders can't
+        // have one
+        match (self.candle_end > 0, submission.candle()) {
+            (true, Some(candle)) => candle.validate(submission.price)?,
+            (false, None) => {}
+            _ => {
+                return Err(error!(ProgramError::InvalidCandle));
+            }
+        }
+
+        if
+            submission.num_sources == 0 ||
+            submission.num_sources != (submission.source_bitmap.count_ones() as u8)
+        {
+            return Err(error!(ProgramError::InvalidPriceSources));
+        }
+
+        let time_deviation = submission.publish_time.abs_diff(self.price_timestamp());
+        if time_deviation > market.max_price_time_deviation.into() {
+            return Err(error!(ProgramError::PriceTimestampOutOfRange));
+        }
+
+        if submission.override_deviation == 0 {
+            market.check_price_deviation(submission.price, submission.publish_time)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record an oracle's price and settle the order once the quorum is reached
+    pub fn submit_price(
+        &mut self,
+        order: Pubkey,
+        market: &mut MarketAccount,
+        order_queue: &mut OrderQueueAccount,
+        price_history: &mut PriceHistoryAccount,
+        submission: OracleSubmission,
+        event_authority: &EventAuthority
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+
+        self.validate_submission(market, &submission, clock.unix_timestamp)?;
+        self.push_submission(submission);
+
+        event_authority.emit(
+            &(OraclePriceSubmittedEvent {
+                market: market.name,
+                order,
+                oracle: submission.oracle,
+                price: submission.price,
+                publish_time: submission.publish_time,
+                confidence: submission.confidence,
+                num_sources: submission.num_sources,
+                source_bitmap: submission.source_bitmap,
+                num_submissions: self.num_submissions,
+                quorum: self.quorum,
+                override_deviation: submission.override_deviation == 1,
+            })
+        )?;
+
+        if self.num_submissions < self.quorum {
+            return Ok(());
+        }
+
+        let (price, median_submission) = self.median();
+
+        self.open_order = 0;
+        self.close_timestamp = clock.unix_timestamp;
+        self.close_slot = clock.slot;
+        self.oracle_price = price;
+        self.price_publish_time = median_submission.publish_time;
+        self.price_confidence = median_submission.confidence;
+        self.num_sources = median_submission.num_sources;
+        self.source_bitmap = median_submission.source_bitmap;
+        self.num_samples = median_submission.num_samples;
+        self.fill_status = self.kind().fill_status(price) as u8;
+        self.set_candle(self.median_candle());
+
+        order_queue.remove(&order, clock.unix_timestamp);
+        market.stats.record(self, &clock);
+
+        // TWAPs are averages so only point prices are recorded, a candle's close is the price at
+        // its end
+        if self.twap_window == 0 {
+            price_history.push(PriceHistoryEntry {
+                timestamp: self.price_timestamp(),
+                price,
+                confidence: self.price_confidence,
+            });
+            market.record_price(price, self.price_publish_time);
+        }
+
+        event_authority.emit(
+            &(OraclePriceFulfilledEvent {
+                market: market.name,
+                order,
+
+                open_timestamp: self.open_timestamp,
+                open_slot: self.open_slot,
+                requested_timestamp: self.requested_timestamp,
+
+                latency_seconds: self.close_timestamp - self.open_timestamp,
+                latency_slots: self.close_slot - self.open_slot,
+
+                price,
+                decimals: market.decimals,
+                publish_time: self.price_publish_time,
+                confidence: self.price_confidence,
+                num_sources: self.num_sources,
+                source_bitmap: self.source_bitmap,
+                num_samples: self.num_samples,
+                fill_status: self.fill_status(),
+                candle: self.candle(),
+            })
+        )?;
+
+        Ok(())
+    }
+
+    pub fn is_expired(&self, timestamp: i64) -> bool {
+        timestamp > self.expiry_timestamp
+    }
+
+    /// Release the latest submitter's share of the escrowed fee. The fee is split evenly across
+    /// the quorum and the submission that completes it receives any remainder.
+    pub fn take_fee_share(&mut self) -> u64 {
+        let share = if self.open_order == 0 {
+            self.fee
+        } else {
+            self.fee / (self.quorum.saturating_sub(self.num_submissions) as u64 + 1)
+        };
+
+        self.fee -= share;
+        share
+    }
+
+    /// The median of the submitted prices, averaging the middle two for an even count, along with
+    /// the submission at the middle of the sorted prices whose publish time, confidence and
+    /// sources are kept
+    pub fn median(&self) -> (u64, OracleSubmission) {
+        let mut submissions = self.submissions().to_vec();
+        submissions.sort_unstable_by_key(|s| s.price);
+
+        let price = median_price(
+            submissions
+                .iter()
+                .map(|s| s.price)
+                .collect()
+        );
+
+        (price, submissions[submissions.len() / 2])
+    }
+
+    /// The median of each component of the submitted candles, None unless this is a candle order
+    pub fn median_candle(&self) -> Option<Ohlc> {
+        if self.candle_end == 0 {
+            return None;
+        }
+
+        let candles: Vec<Ohlc> = self
+            .submissions()
+            .iter()
+            .filter_map(|s| s.candle())
+            .collect();
+        let component = |f: fn(&Ohlc) -> u64| median_price(candles.iter().map(f).collect());
+
+        Some(Ohlc {
+            open: component(|c| c.open),
+            high: component(|c| c.high),
+            low: component(|c| c.low),
+            close: component(|c| c.close),
+        })
+    }
+}
+
+/// The layout orders were deployed with before they were versioned, which migrate_order reads to
+/// convert legacy orders. These orders were created at keypair addresses and didn't record their
+/// payer, nonce or expiry.
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct OrderAccountV0 {
+    pub open_order: u8,
+    pub reserved: [u8; 31],
+    pub authority: Pubkey,
+    pub market: Pubkey,
+    pub market_name: [u8; 8],
+    pub open_timestamp: i64,
+    pub open_slot: u64,
+    pub close_timestamp: i64,
+    pub close_slot: u64,
+    pub oracle_price: u64,
+}
+
+/// The program account as originally deployed, which migrate_program reads. It had a single
+/// oracle and none of the current configs.
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct ProgramAccountV0 {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub oracle: Pubkey,
+    #[max_len(MAX_MARKETS)]
+    pub markets: Vec<[u8; 8]>,
+}
+
+/// A market account as originally deployed, which migrate_market reads
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct MarketAccountV0 {
+    pub bump: u8,
+    pub name: [u8; 8],
+    pub decimals: u32,
+    pub oracle_staleness_threshold: u32,
+}
+
+/// An oracle account as originally deployed, which migrate_oracle reads. It had no fees or stats.
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct OracleAccountV0 {
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub enclave_signer: Pubkey,
+    pub verification_timestamp: i64,
+    pub verification_slot: u64,
+    pub valid_until_slot: u64,
+}
+
+/// The median of a set of prices, averaging the middle two for an even count
+fn median_price(mut prices: Vec<u64>) -> u64 {
+    prices.sort_unstable();
+
+    let mid = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        prices[mid]
+    } else {
+        let (lower, upper) = (prices[mid - 1], prices[mid]);
+        // avoid overflow when averaging two large prices
+        lower / 2 + upper / 2 + (lower % 2 + upper % 2) / 2
+    }
+}
+
+#[zero_copy]
+#[derive(Debug, Default)]
+pub struct OracleSubmission {
+    pub oracle: Pubkey,
+    pub price: u64,
+    pub publish_time: i64,
+    pub confidence: u64,
+    /// The submitted candle for a candle order
+    pub candle: Ohlc,
+    pub num_samples: u32,
+    pub num_sources: u8,
+    pub source_bitmap: u8,
+    pub has_candle: u8,
+    /// Whether the oracle overrode the market's price deviation check
+    pub override_deviation: u8,
+}
+
+impl OracleSubmission {
+    pub fn candle(&self) -> Option<Ohlc> {
+        (self.has_candle == 1).then_some(self.candle)
+    }
+}
+
+/// The open, high, low and close prices of a market over an interval
+#[zero_copy]
+#[derive(Debug, Default, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub struct Ohlc {
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+}
+impl Ohlc {
+    /// Check the high and low bound the open and close, and the candle closes at the price
+    pub fn validate(&self, price: u64) -> Result<()> {
+        if
+            self.close != price ||
+            self.low > std::cmp::min(self.open, self.close) ||
+            self.high < std::cmp::max(self.open, self.close)
+        {
+            return Err(error!(ProgramError::InvalidCandle));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+/// What the order settles. Price orders only record the backfilled price, limit and stop orders
+/// also record whether they filled at that price.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub enum OrderKind {
+    #[default]
+    Price,
+    /// Buys fill at or below the limit price, sells fill at or above it
+    Limit {
+        side: OrderSide,
+        limit_price: u64,
+    },
+    /// Buys trigger at or above the trigger price, sells trigger at or below it
+    Stop {
+        side: OrderSide,
+        trigger_price: u64,
+    },
+}
+
+impl OrderKind {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            OrderKind::Price => Ok(()),
+            | OrderKind::Limit { limit_price: price, .. }
+            | OrderKind::Stop { trigger_price: price, .. } => {
+                if *price == 0 {
+                    return Err(error!(ProgramError::InvalidOrderKind));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Decide whether the order filled at the settled price
+    pub fn fill_status(&self, price: u64) -> FillStatus {
+        let filled = match *self {
+            OrderKind::Price => {
+                return FillStatus::None;
+            }
+            OrderKind::Limit { side: OrderSide::Buy, limit_price } => price <= limit_price,
+            OrderKind::Limit { side: OrderSide::Sell, limit_price } => price >= limit_price,
+            OrderKind::Stop { side: OrderSide::Buy, trigger_price } => price >= trigger_price,
+            OrderKind::Stop { side: OrderSide::Sell, trigger_price } => price <= trigger_price,
+        };
+
+        if filled {
+            FillStatus::Filled
+        } else {
+            FillStatus::Unfilled
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub enum FillStatus {
+    /// Price orders and orders that haven't settled
+    #[default]
+    None,
+    Filled,
+    Unfilled,
+}
+
+impl From<u8> for FillStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            1 => FillStatus::Filled,
+            2 => FillStatus::Unfilled,
+            _ => FillStatus::None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub enum DisputeStatus {
+    #[default]
+    None,
+    Disputed,
+    Upheld,
+    Rejected,
+}
+
+impl From<u8> for DisputeStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            1 => DisputeStatus::Disputed,
+            2 => DisputeStatus::Upheld,
+            3 => DisputeStatus::Rejected,
+            _ => DisputeStatus::None,
+        }
+    }
+}
+
+/// A challenger's counter-price against one oracle's submission on a fulfilled order
+#[zero_copy]
+#[derive(Debug, Default)]
+pub struct OrderDispute {
+    /// The DisputeStatus
+    pub status: u8,
+    pub reserved: [u8; 7],
+    pub challenger: Pubkey,
+    pub oracle: Pubkey,
+    pub price: u64,
+    pub publish_time: i64,
+    /// A hash of the off-chain evidence backing the counter-price
+    pub evidence: [u8; 32],
+    pub timestamp: i64,
+    pub slashed_amount: u64,
+}
+
+impl OrderDispute {
+    pub fn status(&self) -> DisputeStatus {
+        self.status.into()
+    }
+}
+
+/// An instruction to invoke on the requesting program once the order settles
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct OrderCallback {
+    pub program_id: Pubkey,
+    /// Prepended to the serialized FulfilledPrice to form the instruction data
+    pub discriminator: [u8; 8],
+    /// Passed after the callback authority, which signs the instruction, and the order account
+    #[max_len(MAX_CALLBACK_ACCOUNTS)]
+    pub accounts: Vec<CallbackAccount>,
+}
+
+impl OrderCallback {
+    pub fn validate(&self) -> Result<()> {
+        if self.program_id == crate::ID || self.accounts.len() > MAX_CALLBACK_ACCOUNTS {
+            return Err(error!(ProgramError::InvalidCallback));
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&OrderCallbackData> for OrderCallback {
+    fn from(callback: &OrderCallbackData) -> Self {
+        OrderCallback {
+            program_id: callback.program_id,
+            discriminator: callback.discriminator,
+            accounts: callback.accounts[..callback.num_accounts as usize]
+                .iter()
+                .map(|account| CallbackAccount {
+                    pubkey: account.pubkey,
+                    is_writable: account.is_writable == 1,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct CallbackAccount {
+    pub pubkey: Pubkey,
+    pub is_writable: bool,
+}
+
+/// An OrderCallback stored inline in the order account
+#[zero_copy]
+#[derive(Debug, Default)]
+pub struct OrderCallbackData {
+    pub program_id: Pubkey,
+    pub discriminator: [u8; 8],
+    pub num_accounts: u8,
+    pub reserved: [u8; 7],
+    pub accounts: [CallbackAccountData; MAX_CALLBACK_ACCOUNTS],
+}
+
+impl From<&OrderCallback> for OrderCallbackData {
+    fn from(callback: &OrderCallback) -> Self {
+        let mut data = OrderCallbackData {
+            program_id: callback.program_id,
+            discriminator: callback.discriminator,
+            num_accounts: callback.accounts.len() as u8,
+            ..Default::default()
+        };
+        for (slot, account) in data.accounts.iter_mut().zip(callback.accounts.iter()) {
+            slot.pubkey = account.pubkey;
+            slot.is_writable = account.is_writable.into();
+        }
+
+        data
+    }
+}
+
+#[zero_copy]
+#[derive(Debug, Default)]
+pub struct CallbackAccountData {
+    pub pubkey: Pubkey,
+    pub is_writable: u8,
+}
+
+/// The instruction data a callback program receives after the discriminator
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct FulfilledPrice {
+    pub order: Pubkey,
+    pub market: [u8; 8],
+    pub price: u64,
+    pub decimals: u32,
+    pub publish_time: i64,
+    pub confidence: u64,
+    pub open_timestamp: i64,
+    pub requested_timestamp: i64,
+    pub num_samples: u32,
+    pub fill_status: FillStatus,
+    pub candle: Option<Ohlc>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct InitializeParams {
+    pub attestation_validity_slots: u64,
+    pub oracle_quorum: u8,
+    pub order_close_grace_period: i64,
+    pub dispute_window: i64,
+    pub min_stake: u64,
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + ProgramAccount::INIT_SPACE,
+        seeds = [b"PROGRAM"],
+        bump
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    /// CHECK:
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct AddMarketParams {
+    pub name: [u8; 8],
+    pub decimals: u32,
+    pub oracle_staleness_threshold: u32,
+    pub pyth_price_feed: Pubkey,
+    pub order_ttl: u32,
+    pub max_price_time_deviation: u32,
+    pub order_fee: u64,
+    pub max_lookback: u32,
+    pub max_open_orders: u32,
+    pub order_deposit: u64,
+    pub max_deviation_bps_per_second: u32,
+}
+
+#[derive(Accounts)]
+#[instruction(params: AddMarketParams)] // rpc parameters hint
+pub struct AddMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + MarketAccount::INIT_SPACE,
+        seeds = [program.key().to_bytes().as_ref(), params.name.as_ref()],
+        bump
+    )]
+    pub market: Account<'info, MarketAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<OrderQueueAccount>(),
+        seeds = [b"QUEUE", market.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub order_queue: AccountLoader<'info, OrderQueueAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PriceHistoryAccount>(),
+        seeds = [b"HISTORY", market.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistoryAccount>,
+
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateMarketConfigParams {
+    pub market: [u8; 8],
+    pub decimals: Option<u32>,
+    pub oracle_staleness_threshold: Option<u32>,
+    pub pyth_price_feed: Option<Pubkey>,
+    pub order_ttl: Option<u32>,
+    pub max_price_time_deviation: Option<u32>,
+    pub paused: Option<bool>,
+    pub order_fee: Option<u64>,
+    pub max_lookback: Option<u32>,
+    pub max_open_orders: Option<u32>,
+    pub order_deposit: Option<u64>,
+    pub max_deviation_bps_per_second: Option<u32>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: UpdateMarketConfigParams)] // rpc parameters hint
+pub struct UpdateMarketConfig<'info> {
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        mut,
+        seeds = [program.key().to_bytes().as_ref(), params.market.as_ref()],
+        bump = market.bump,
+        has_one = price_history,
+    )]
+    pub market: Account<'info, MarketAccount>,
+
+    #[account(mut)]
+    pub price_history: AccountLoader<'info, PriceHistoryAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateProgramConfigParams {
+    pub oracle_quorum: Option<u8>,
+    pub min_stake: Option<u64>,
+}
+
+#[derive(Accounts)]
+#[instruction(params: UpdateProgramConfigParams)] // rpc parameters hint
+pub struct UpdateProgramConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct TransferAuthorityParams {
+    pub new_authority: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(params: TransferAuthorityParams)] // rpc parameters hint
+pub struct TransferAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        constraint = program.pending_authority == pending_authority.key() @ ProgramError::InvalidPendingAuthority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterOracle<'info> {
+    #[account(seeds = [b"PROGRAM"], bump = program.bump)]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OracleAccount::INIT_SPACE,
+        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    pub enclave_signer: Signer<'info>,
+
+    /// CHECK:
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct AddOracleParams {
+    pub oracle: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(params: AddOracleParams)] // rpc parameters hint
+pub struct AddOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        seeds = [b"STAKE", params.oracle.to_bytes().as_ref()],
+        bump = stake.bump,
+        constraint = stake.amount >= program.min_stake @ ProgramError::InsufficientStake
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct RemoveOracleParams {
+    pub oracle: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(params: RemoveOracleParams)] // rpc parameters hint
+pub struct RemoveOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct AddChallengerParams {
+    pub challenger: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(params: AddChallengerParams)] // rpc parameters hint
+pub struct AddChallenger<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct RemoveChallengerParams {
+    pub challenger: Pubkey,
+}
+
+#[derive(Accounts)]
+#[instruction(params: RemoveChallengerParams)] // rpc parameters hint
+pub struct RemoveChallenger<'info> {
+    #[account(
+        mut,
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        has_one = authority,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct DepositStakeParams {
+    pub amount: u64,
+}
+
+#[derive(Accounts)]
+#[instruction(params: DepositStakeParams)] // rpc parameters hint
+pub struct DepositStake<'info> {
+    #[account(
+        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
+        bump = oracle.bump,
+        has_one = authority,
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + StakeAccount::INIT_SPACE,
+        seeds = [b"STAKE", oracle.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct WithdrawStakeParams {
+    pub amount: u64,
+}
+
+#[derive(Accounts)]
+#[instruction(params: WithdrawStakeParams)] // rpc parameters hint
+pub struct WithdrawStake<'info> {
+    #[account(
+        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
+        bump = oracle.bump,
+        has_one = authority,
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"STAKE", oracle.key().to_bytes().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: any account the authority wants the stake sent to
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Heartbeat<'info> {
+    #[account(seeds = [b"PROGRAM"], bump = program.bump)]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
+        bump = oracle.bump,
+        has_one = authority,
+        has_one = enclave_signer,
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    pub enclave_signer: Signer<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct CreateOrderParams {
+    pub market: [u8; 8],
+    /// A past timestamp to price the order at, within the market's max_lookback
+    pub timestamp: Option<i64>,
+    pub kind: OrderKind,
+    pub callback: Option<OrderCallback>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(params: CreateOrderParams)] // rpc parameters hint
+pub struct CreateOrder<'info> {
+    pub new_order: NewOrder<'info>,
+
+    /// CHECK: validated against the market config and deserialized by the Pyth SDK
+    #[account(address = new_order.market.pyth_price_feed @ ProgramError::InvalidPythPriceFeed)]
+    pub pyth_price: AccountInfo<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct CreateTwapOrderParams {
+    pub market: [u8; 8],
+    /// Seconds to average the price over, starting from the order's open time
+    pub window: u32,
+    pub callback: Option<OrderCallback>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(params: CreateTwapOrderParams)] // rpc parameters hint
+pub struct CreateTwapOrder<'info> {
+    pub new_order: NewOrder<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct CreateCandleOrderParams {
+    pub market: [u8; 8],
+    /// Start of the candle's interval, within the market's lookback
+    pub start: i64,
+    /// End of the candle's interval, which may be in the future
+    pub end: i64,
+    pub callback: Option<OrderCallback>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(params: CreateCandleOrderParams)] // rpc parameters hint
+pub struct CreateCandleOrder<'info> {
+    pub new_order: NewOrder<'info>,
+}
+
+/// The accounts every create instruction opens an order with. Each create params struct starts
+/// with the market name, which is all the seeds here need.
+#[derive(Accounts)]
+#[instruction(market_name: [u8; 8])] // rpc parameters hint
+pub struct NewOrder<'info> {
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        constraint = program.oracles.len() >= program.oracle_quorum as usize @ ProgramError::InsufficientOracles
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        seeds = [program.key().to_bytes().as_ref(), market_name.as_ref()],
+        bump = market.bump,
+        has_one = order_queue,
+        constraint = !market.paused @ ProgramError::MarketPaused
+    )]
+    pub market: Account<'info, MarketAccount>,
+
+    #[account(mut)]
+    pub order_queue: AccountLoader<'info, OrderQueueAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserAccount::INIT_SPACE,
+        seeds = [b"USER", authority.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub user: Account<'info, UserAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + RateLimitAccount::INIT_SPACE,
+        seeds = [
+            b"RATE_LIMIT",
+            market.key().to_bytes().as_ref(),
+            authority.key().to_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub rate_limit: Account<'info, RateLimitAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<OrderAccount>(),
+        seeds = [
+            market.key().to_bytes().as_ref(),
+            authority.key().to_bytes().as_ref(),
+            user.nonce.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub order: AccountLoader<'info, OrderAccount>,
+
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> NewOrder<'info> {
+    /// Open the next order for the authority, setting up its user and rate limit accounts on
+    /// first use. A fresh Pyth price settles the order on the spot, otherwise the fee is escrowed
+    /// and the order is queued for the oracles.
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        &mut self,
+        bumps: &NewOrderBumps,
+        requested_timestamp: i64,
+        twap_window: u32,
+        candle_end: i64,
+        kind: OrderKind,
+        callback: Option<&OrderCallback>,
+        pyth_price: Option<Price>,
+        clock: &Clock,
+        event_authority: &EventAuthority
+    ) -> Result<()> {
+        if self.user.bump == 0 {
+            self.user.bump = bumps.user;
+            self.user.authority = self.authority.key();
+        }
+
+        if self.rate_limit.bump == 0 {
+            self.rate_limit.bump = bumps.rate_limit;
+            self.rate_limit.market = self.market.key();
+            self.rate_limit.authority = self.authority.key();
+        }
+
+        if let Some(callback) = callback {
+            callback.validate()?;
+        }
+
+        if pyth_price.is_none() {
+            escrow_order_fee(&self.order, &self.market, &self.payer, &self.system_program)?;
+        }
+
+        let mut order = self.order.load_init()?;
+        order.open(
+            &self.market,
+            self.program.oracle_quorum,
+            requested_timestamp,
+            twap_window,
+            candle_end,
+            clock
+        );
+        order.authority = self.authority.key();
+        order.payer = self.payer.key();
+        order.bump = bumps.order;
+        order.nonce = self.user.next_nonce()?;
+        order.set_kind(kind);
+        order.set_callback(callback);
+
+        if let Some(pyth_price) = pyth_price {
+            let price = self.market.scale_price(
+                pyth_price.price.try_into().map_err(|_| error!(ProgramError::InvalidPrice))?,
+                pyth_price.expo
+            )?;
+            let confidence = self.market.scale_price(pyth_price.conf, pyth_price.expo)?;
+
+            order.open_order = 0;
+            order.close_timestamp = clock.unix_timestamp;
+            order.close_slot = clock.slot;
+            order.oracle_price = price;
+            order.price_publish_time = pyth_price.publish_time;
+            order.price_confidence = confidence;
+            order.num_sources = 1;
+            order.source_bitmap = SOURCE_PYTH;
+            order.num_samples = 1;
+            order.fill_status = order.kind().fill_status(price) as u8;
+
+            return event_authority.emit(
+                &(OraclePriceFulfilledEvent {
+                    market: self.market.name,
+                    order: self.order.key(),
+
+                    open_timestamp: order.open_timestamp,
+                    open_slot: order.open_slot,
+                    requested_timestamp,
+
+                    latency_seconds: 0,
+                    latency_slots: 0,
+
+                    price,
+                    decimals: self.market.decimals,
+                    publish_time: pyth_price.publish_time,
+                    confidence,
+                    num_sources: 1,
+                    source_bitmap: SOURCE_PYTH,
+                    num_samples: 1,
+                    fill_status: order.fill_status(),
+                    candle: None,
+                })
+            );
+        }
+
+        request_oracle_price(
+            self.order.key(),
+            &mut order,
+            &self.market,
+            &mut self.rate_limit,
+            &mut *self.order_queue.load_mut()?,
+            clock,
+            event_authority
+        )
+    }
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct FulfillOrderParams {
+    pub market: [u8; 8],
+    pub price: u64,
+    /// The source publish time of the price
+    pub publish_time: i64,
+    pub confidence: u64,
+    pub num_sources: u8,
+    /// A bitmap of the SOURCE_* providers that contributed to the price
+    pub source_bitmap: u8,
+    /// Number of per-second prices averaged for a TWAP order or aggregated into a candle, 1 for
+    /// a point price
+    pub num_samples: u32,
+    /// The candle for a candle order, its close must match the price
+    pub candle: Option<Ohlc>,
+    /// Accept a price beyond the market's max_deviation_bps_per_second of its last fulfilled
+    /// price, recorded on the submission and in the OraclePriceSubmittedEvent
+    pub override_deviation: bool,
+}
+impl FulfillOrderParams {
+    pub fn to_submission(&self, oracle: Pubkey) -> OracleSubmission {
+        OracleSubmission {
+            oracle,
+            price: self.price,
+            publish_time: self.publish_time,
+            confidence: self.confidence,
+            candle: self.candle.unwrap_or_default(),
+            num_samples: self.num_samples,
+            num_sources: self.num_sources,
+            source_bitmap: self.source_bitmap,
+            has_candle: self.candle.is_some().into(),
+            override_deviation: self.override_deviation.into(),
+        }
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(params: FulfillOrderParams)] // rpc parameters hint
+pub struct FulfillOrder<'info> {
+    #[account(
+        mut,
+        constraint = OrderAccount::is_current(order.as_ref())? @ ProgramError::OrderNotMigrated,
+        constraint = order.load()?.open_order == 1 @ ProgramError::OrderAlreadyFulfilled,
+        constraint = order.load()?.market == market.key() @ ErrorCode::ConstraintHasOne,
+    )]
+    pub order: AccountLoader<'info, OrderAccount>,
+
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program_config.bump,
+        constraint = program_config.oracles.contains(&oracle.key()) @ ProgramError::OracleNotRegistered
+    )]
+    pub program_config: Account<'info, ProgramAccount>,
+
+    #[account(
+        mut,
+        seeds = [program_config.key().to_bytes().as_ref(), params.market.as_ref()],
+        bump = market.bump,
+        has_one = order_queue,
+        has_one = price_history,
+    )]
+    pub market: Account<'info, MarketAccount>,
+
+    #[account(mut)]
+    pub order_queue: AccountLoader<'info, OrderQueueAccount>,
+
+    #[account(mut)]
+    pub price_history: AccountLoader<'info, PriceHistoryAccount>,
+
+    /// CHECK: the order authority's RateLimitAccount in the market, only loaded if the order is
+    /// counted in it
+    #[account(
+        mut,
+        seeds = [
+            b"RATE_LIMIT",
+            market.key().to_bytes().as_ref(),
+            order.load()?.authority.to_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub rate_limit: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        has_one = enclave_signer,
+        constraint = !oracle.is_expired(Clock::get()?.slot) @ ProgramError::OracleAttestationExpired
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"STAKE", oracle.key().to_bytes().as_ref()],
+        bump = stake.bump,
+        constraint = stake.amount >= program_config.min_stake @ ProgramError::InsufficientStake
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub enclave_signer: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct FulfillOrdersParams {
+    pub orders: Vec<FulfillOrderParams>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct FulfillOrders<'info> {
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program_config.bump,
+        constraint = program_config.oracles.contains(&oracle.key()) @ ProgramError::OracleNotRegistered
+    )]
+    pub program_config: Account<'info, ProgramAccount>,
+
+    #[account(
+        mut,
+        has_one = enclave_signer,
+        constraint = !oracle.is_expired(Clock::get()?.slot) @ ProgramError::OracleAttestationExpired
+    )]
+    pub oracle: Account<'info, OracleAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"STAKE", oracle.key().to_bytes().as_ref()],
+        bump = stake.bump,
+        constraint = stake.amount >= program_config.min_stake @ ProgramError::InsufficientStake
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub enclave_signer: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct GetHistoricalPriceParams {
+    pub market: [u8; 8],
+    pub timestamp: i64,
+    /// Furthest in seconds the recorded price can be before the timestamp
+    pub max_age: u32,
+}
+
+#[derive(Accounts)]
+#[instruction(params: GetHistoricalPriceParams)] // rpc parameters hint
+pub struct GetHistoricalPrice<'info> {
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    #[account(
+        seeds = [program.key().to_bytes().as_ref(), params.market.as_ref()],
+        bump = market.bump,
+        has_one = price_history,
+    )]
+    pub market: Account<'info, MarketAccount>,
+
+    pub price_history: AccountLoader<'info, PriceHistoryAccount>,
 }
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct CloseOrder<'info> {
     #[account(
-        init,
-        payer = payer,
-        space = 8 + ProgramAccount::INIT_SPACE,
-        seeds = [b"PROGRAM"],
-        bump
+        mut,
+        close = payer,
+        constraint = OrderAccount::is_current(order.as_ref())? @ ProgramError::OrderNotMigrated,
+        constraint = order.load()?.open_order == 0 @ ProgramError::OrderNotFulfilled,
+        constraint = order.load()?.payer == payer.key() @ ErrorCode::ConstraintHasOne,
     )]
+    pub order: AccountLoader<'info, OrderAccount>,
+
+    #[account(seeds = [b"PROGRAM"], bump = program.bump)]
     pub program: Account<'info, ProgramAccount>,
 
+    /// CHECK: validated against the order's payer
+    #[account(mut)]
+    pub payer: AccountInfo<'info>,
+
+    pub closer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
     #[account(
-        init,
-        payer = payer,
-        space = 8 + MarketAccount::INIT_SPACE,
-        seeds = [program.key().to_bytes().as_ref(), b"BTC\0\0\0\0\0"],
-        bump
+        mut,
+        close = payer,
+        constraint = OrderAccount::is_current(order.as_ref())? @ ProgramError::OrderNotMigrated,
+        constraint = order.load()?.open_order == 1 @ ProgramError::OrderAlreadyFulfilled,
+        constraint = order.load()?.authority == authority.key() @ ErrorCode::ConstraintHasOne,
+        constraint = order.load()?.payer == payer.key() @ ErrorCode::ConstraintHasOne,
+        constraint = order.load()?.market == market.key() @ ErrorCode::ConstraintHasOne,
     )]
-    pub btc_market: Account<'info, MarketAccount>,
+    pub order: AccountLoader<'info, OrderAccount>,
 
+    #[account(has_one = order_queue)]
+    pub market: Account<'info, MarketAccount>,
+
+    #[account(mut)]
+    pub order_queue: AccountLoader<'info, OrderQueueAccount>,
+
+    /// CHECK: the authority's RateLimitAccount in the market, only loaded if the order is counted
+    /// in it
     #[account(
-        init,
-        payer = payer,
-        space = 8 + MarketAccount::INIT_SPACE,
-        seeds = [program.key().to_bytes().as_ref(), b"ETH\0\0\0\0\0"],
+        mut,
+        seeds = [
+            b"RATE_LIMIT",
+            market.key().to_bytes().as_ref(),
+            authority.key().to_bytes().as_ref(),
+        ],
         bump
     )]
-    pub eth_market: Account<'info, MarketAccount>,
+    pub rate_limit: AccountInfo<'info>,
 
+    pub authority: Signer<'info>,
+
+    /// CHECK: validated against the order's payer
+    #[account(mut)]
+    pub payer: AccountInfo<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct DisputeOrderParams {
+    pub price: u64,
+    pub publish_time: i64,
+    pub evidence: [u8; 32],
+}
+
+#[derive(Accounts)]
+#[instruction(params: DisputeOrderParams)] // rpc parameters hint
+pub struct DisputeOrder<'info> {
     #[account(
-        init,
-        payer = payer,
-        space = 8 + MarketAccount::INIT_SPACE,
-        seeds = [program.key().to_bytes().as_ref(), b"SOL\0\0\0\0\0"],
-        bump
+        mut,
+        constraint = OrderAccount::is_current(order.as_ref())? @ ProgramError::OrderNotMigrated,
+        constraint = order.load()?.open_order == 0 @ ProgramError::OrderNotFulfilled,
+        constraint = order.load()?.dispute.status() == DisputeStatus::None @ ProgramError::OrderAlreadyDisputed,
     )]
-    pub sol_market: Account<'info, MarketAccount>,
+    pub order: AccountLoader<'info, OrderAccount>,
 
-    /// CHECK:
-    pub authority: AccountInfo<'info>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [b"PROGRAM"],
+        bump = program.bump,
+        constraint = program.challengers.contains(&challenger.key()) @ ProgramError::ChallengerNotRegistered
+    )]
+    pub program: Account<'info, ProgramAccount>,
+
+    /// The stake account of the oracle whose submission is disputed
+    #[account(
+        mut,
+        seeds = [b"STAKE", stake.oracle.to_bytes().as_ref()],
+        bump = stake.bump,
+    )]
+    pub stake: Account<'info, StakeAccount>,
+
+    pub challenger: Signer<'info>,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct ResolveDisputeParams {
+    pub upheld: bool,
+    pub slash_amount: u64,
 }
 
 #[derive(Accounts)]
-pub struct RegisterOracle<'info> {
+#[instruction(params: ResolveDisputeParams)] // rpc parameters hint
+pub struct ResolveDispute<'info> {
     #[account(
         mut,
+        constraint = OrderAccount::is_current(order.as_ref())? @ ProgramError::OrderNotMigrated,
+        constraint = order.load()?.dispute.status() == DisputeStatus::Disputed @ ProgramError::OrderNotDisputed,
+        constraint = order.load()?.dispute.challenger == challenger.key() @ ProgramError::InvalidChallenger,
+    )]
+    pub order: AccountLoader<'info, OrderAccount>,
+
+    #[account(
         seeds = [b"PROGRAM"],
         bump = program.bump,
-        // has_one = oracle,
+        has_one = authority,
     )]
     pub program: Account<'info, ProgramAccount>,
 
     #[account(
-        init_if_needed,
-        payer = payer,
-        space = 8 + OracleAccount::INIT_SPACE,
-        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
-        bump
+        mut,
+        seeds = [b"STAKE", order.load()?.dispute.oracle.to_bytes().as_ref()],
+        bump = stake.bump,
     )]
-    pub oracle: Account<'info, OracleAccount>,
+    pub stake: Account<'info, StakeAccount>,
 
-    pub enclave_signer: Signer<'info>,
+    /// CHECK: validated against the order's dispute
+    #[account(mut)]
+    pub challenger: AccountInfo<'info>,
 
-    /// CHECK:
     pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateOrder<'info> {
+    /// CHECK: a legacy order, its discriminator and version are checked in migrate_order
+    #[account(mut, owner = crate::ID)]
+    pub order: AccountInfo<'info>,
+
     #[account(mut)]
     pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
-#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
-pub struct CreateOrderParams {
-    pub market: MarketType,
+#[derive(Accounts)]
+pub struct MigrateProgram<'info> {
+    /// CHECK: the legacy program account, its discriminator and size are checked in
+    /// migrate_program
+    #[account(mut, seeds = [b"PROGRAM"], bump)]
+    pub program: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(params: CreateOrderParams)] // rpc parameters hint
-pub struct CreateOrder<'info> {
-    #[account(init, payer = payer, space = 8 + OrderAccount::INIT_SPACE)]
-    pub order: Account<'info, OrderAccount>,
-
+#[instruction(params: AddMarketParams)] // rpc parameters hint
+pub struct MigrateMarket<'info> {
     #[account(
         seeds = [b"PROGRAM"],
         bump = program.bump,
-        constraint = program.oracle != Pubkey::default() @ ProgramError::OracleAlreadyRegistered
+        has_one = authority,
     )]
     pub program: Account<'info, ProgramAccount>,
 
+    /// CHECK: a legacy market account, its discriminator and size are checked in migrate_market
+    #[account(
+        mut,
+        seeds = [program.key().to_bytes().as_ref(), params.name.as_ref()],
+        bump
+    )]
+    pub market: AccountInfo<'info>,
+
     #[account(
-        seeds = [program.key().to_bytes().as_ref(), params.market.to_bytes().as_ref()],
-        bump = market.bump
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<OrderQueueAccount>(),
+        seeds = [b"QUEUE", market.key().to_bytes().as_ref()],
+        bump
     )]
-    pub market: Account<'info, MarketAccount>,
+    pub order_queue: AccountLoader<'info, OrderQueueAccount>,
 
-    /// CHECK:
-    pub authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PriceHistoryAccount>(),
+        seeds = [b"HISTORY", market.key().to_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistoryAccount>,
+
+    pub authority: Signer<'info>,
     #[account(mut)]
     pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
-#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
-pub struct FulfillOrderParams {
-    pub market: MarketType,
-    pub price: u64,
+#[derive(Accounts)]
+pub struct MigrateOracle<'info> {
+    /// CHECK: a legacy oracle account, its discriminator and size are checked in migrate_oracle
+    #[account(mut, owner = crate::ID)]
+    pub oracle: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(params: FulfillOrderParams)] // rpc parameters hint
-pub struct FulfillOrder<'info> {
+pub struct WithdrawFees<'info> {
     #[account(
         mut,
-        constraint = order.open_order == 1 @ ProgramError::OrderAlreadyFulfilled,
-        has_one = market,
+        seeds = [b"ORACLE", authority.key().to_bytes().as_ref()],
+        bump = oracle.bump,
+        has_one = authority,
     )]
-    pub order: Account<'info, OrderAccount>,
+    pub oracle: Account<'info, OracleAccount>,
 
-    #[account(seeds = [b"PROGRAM"], bump = program.bump, has_one = oracle)]
-    pub program: Account<'info, ProgramAccount>,
+    pub authority: Signer<'info>,
 
-    #[account(
-        seeds = [program.key().to_bytes().as_ref(), params.market.to_bytes().as_ref()],
-        bump = market.bump
-    )]
-    pub market: Account<'info, MarketAccount>,
+    /// CHECK: any account the authority wants the fees sent to
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+}
 
-    #[account(has_one = enclave_signer)]
-    pub oracle: Account<'info, OracleAccount>,
+#[event]
+#[derive(Debug)]
+pub struct MarketAddedEvent {
+    pub market: Pubkey,
+    pub name: [u8; 8],
+}
 
-    pub enclave_signer: Signer<'info>,
+#[event]
+#[derive(Debug)]
+pub struct ProgramConfigUpdatedEvent {
+    pub oracle_quorum: u8,
+    pub num_oracles: u8,
+    pub min_stake: u64,
 }
 
 #[event]
 #[derive(Debug)]
-pub struct OraclePriceRequestedEvent {
-    pub market: MarketType,
+pub struct MarketConfigUpdatedEvent {
+    pub market: Pubkey,
+    pub name: [u8; 8],
+    pub decimals: u32,
+    pub oracle_staleness_threshold: u32,
+    pub paused: bool,
+    pub order_fee: u64,
+    pub max_open_orders: u32,
+    pub order_deposit: u64,
+    pub max_deviation_bps_per_second: u32,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct AuthorityTransferredEvent {
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OrderClosedEvent {
+    pub order: Pubkey,
+    pub market: [u8; 8],
+    pub closer: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OrderCancelledEvent {
+    pub order: Pubkey,
+    pub market: [u8; 8],
+    pub authority: Pubkey,
+    pub open_timestamp: i64,
+    pub expiry_timestamp: i64,
+    pub num_submissions: u8,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OracleHeartbeatEvent {
     pub oracle: Pubkey,
+    pub enclave_signer: Pubkey,
+    pub valid_until_slot: u64,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OraclePriceRequestedEvent {
+    pub market: [u8; 8],
+    pub quorum: u8,
     pub order: Pubkey,
+    pub authority: Pubkey,
     pub timestamp: i64,
     pub slot: u64,
+    pub expiry_timestamp: i64,
+    pub twap_window: u32,
+    pub candle_end: i64,
+    pub fee: u64,
+    pub callback: Option<OrderCallback>,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OraclePriceSubmittedEvent {
+    pub market: [u8; 8],
+    pub order: Pubkey,
+    pub oracle: Pubkey,
+    pub price: u64,
+    pub publish_time: i64,
+    pub confidence: u64,
+    pub num_sources: u8,
+    pub source_bitmap: u8,
+    pub num_submissions: u8,
+    pub quorum: u8,
+    pub override_deviation: bool,
 }
 
 #[event]
 #[derive(Debug)]
 pub struct OraclePriceFulfilledEvent {
-    pub market: MarketType,
+    pub market: [u8; 8],
     pub order: Pubkey,
     pub open_timestamp: i64,
     pub open_slot: u64,
+    pub requested_timestamp: i64,
     pub latency_seconds: i64,
     pub latency_slots: u64,
     pub price: u64,
     pub decimals: u32,
+    pub publish_time: i64,
+    pub confidence: u64,
+    pub num_sources: u8,
+    pub source_bitmap: u8,
+    pub num_samples: u32,
+    pub fill_status: FillStatus,
+    pub candle: Option<Ohlc>,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct FeesWithdrawnEvent {
+    pub oracle: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct StakeDepositedEvent {
+    pub oracle: Pubkey,
+    pub amount: u64,
+    pub total: u64,
+}
+
+#[event]
+#[derive(Debug)]
+pub struct OrderDisputedEvent {
+    pub order: Pubkey,
+    pub market: [u8; 8],
+    pub challenger: Pubkey,
+    pub oracle: Pubkey,
+    pub oracle_price: u64,
+    pub price: u64,
+    pub publish_time: i64,
+    pub evidence: [u8; 32],
+}
+
+#[event]
+#[derive(Debug)]
+pub struct DisputeResolvedEvent {
+    pub order: Pubkey,
+    pub market: [u8; 8],
+    pub challenger: Pubkey,
+    pub oracle: Pubkey,
+    pub upheld: bool,
+    pub slashed_amount: u64,
 }
 
 #[error_code]
@@ -366,4 +3484,296 @@ pub enum ProgramError {
     OracleAlreadyRegistered,
     OrderAlreadyFulfilled,
     InvalidMarketName,
+    MaxMarketsReached,
+    InvalidPythPriceFeed,
+    InvalidPrice,
+    InvalidAttestationWindow,
+    OracleAttestationExpired,
+    InvalidOracleQuorum,
+    MaxOraclesReached,
+    OracleNotRegistered,
+    InsufficientOracles,
+    DuplicateOracleSubmission,
+    InvalidRemainingAccounts,
+    OrderNotFulfilled,
+    OrderGracePeriodActive,
+    InvalidOrderTtl,
+    OrderExpired,
+    OrderNotExpired,
+    PriceTimestampOutOfRange,
+    InvalidPriceSources,
+    MarketPaused,
+    InvalidPendingAuthority,
+    NonceOverflow,
+    OrderQueueFull,
+    InvalidFee,
+    NoFeesToWithdraw,
+    InvalidCallback,
+    InvalidCallbackAccounts,
+    ChallengerAlreadyRegistered,
+    ChallengerNotRegistered,
+    MaxChallengersReached,
+    InvalidStakeAmount,
+    StakeLocked,
+    DisputeWindowElapsed,
+    OracleSubmissionNotFound,
+    OrderAlreadyDisputed,
+    OrderNotDisputed,
+    OrderDisputed,
+    InvalidChallenger,
+    InvalidOrderKind,
+    InvalidTwapWindow,
+    PriceWindowNotElapsed,
+    InvalidSampleCount,
+    InvalidOrderTimestamp,
+    InvalidCandleInterval,
+    InvalidCandle,
+    PriceHistoryNotFound,
+    OrderNotMigrated,
+    OrderAlreadyMigrated,
+    OpenOrderLimitReached,
+    InvalidRateLimit,
+    PriceDeviationExceeded,
+    DisputeWindowActive,
+    InsufficientStake,
+    AccountAlreadyMigrated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued_order(expiry_timestamp: i64) -> QueuedOrder {
+        QueuedOrder {
+            order: Pubkey::new_unique(),
+            open_timestamp: 0,
+            expiry_timestamp,
+        }
+    }
+
+    fn submission(price: u64, publish_time: i64) -> OracleSubmission {
+        OracleSubmission {
+            oracle: Pubkey::new_unique(),
+            price,
+            publish_time,
+            num_samples: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn median_price_averages_the_middle_two_for_an_even_count() {
+        assert_eq!(median_price(vec![7]), 7);
+        assert_eq!(median_price(vec![30, 10, 20]), 20);
+        assert_eq!(median_price(vec![40, 10, 30, 20]), 25);
+        assert_eq!(median_price(vec![u64::MAX, u64::MAX - 2]), u64::MAX - 1);
+    }
+
+    #[test]
+    fn order_median_keeps_the_middle_submission() {
+        let mut order: OrderAccount = bytemuck::Zeroable::zeroed();
+        order.push_submission(submission(300, 3));
+        order.push_submission(submission(100, 1));
+        order.push_submission(submission(200, 2));
+
+        let (price, median_submission) = order.median();
+        assert_eq!(price, 200);
+        assert_eq!(median_submission.publish_time, 2);
+    }
+
+    #[test]
+    fn order_queue_reclaims_slots_behind_an_open_head() {
+        let mut queue: OrderQueueAccount = bytemuck::Zeroable::zeroed();
+
+        // A long-lived order, like a TWAP, stays at the head while the orders behind it settle
+        let head = queued_order(3_600);
+        queue.push(head, 0).unwrap();
+        for _ in 0..ORDER_QUEUE_CAPACITY * 2 {
+            let order = queued_order(300);
+            queue.push(order, 0).unwrap();
+            queue.remove(&order.order, 0);
+        }
+        assert_eq!(queue.len, 1);
+
+        // The queue still fills to capacity around the open head
+        for _ in 1..ORDER_QUEUE_CAPACITY {
+            queue.push(queued_order(300), 0).unwrap();
+        }
+        assert!(queue.push(queued_order(300), 0).is_err());
+        assert_eq!(queue.open_orders(0)[0].order, head.order);
+    }
+
+    #[test]
+    fn order_queue_drops_expired_orders_anywhere() {
+        let mut queue: OrderQueueAccount = bytemuck::Zeroable::zeroed();
+
+        let head = queued_order(3_600);
+        queue.push(head, 0).unwrap();
+        for _ in 1..ORDER_QUEUE_CAPACITY {
+            queue.push(queued_order(300), 0).unwrap();
+        }
+        assert!(queue.push(queued_order(300), 0).is_err());
+
+        // Once the orders behind the head expire the next push reclaims their slots
+        let order = queued_order(3_600);
+        queue.push(order, 301).unwrap();
+        assert_eq!(queue.len, 2);
+        let open_orders = queue.open_orders(301);
+        assert_eq!(open_orders[0].order, head.order);
+        assert_eq!(open_orders[1].order, order.order);
+    }
+
+    #[test]
+    fn fee_shares_add_up_to_the_escrowed_fee() {
+        let mut order: OrderAccount = bytemuck::Zeroable::zeroed();
+        order.open_order = 1;
+        order.quorum = 3;
+        order.fee = 100;
+
+        let mut shares = Vec::new();
+        for i in 0..3 {
+            order.push_submission(submission(100, i));
+            if order.num_submissions == order.quorum {
+                order.open_order = 0;
+            }
+            shares.push(order.take_fee_share());
+        }
+
+        // The submission that completes the quorum receives the remainder
+        assert_eq!(shares, vec![33, 33, 34]);
+        assert_eq!(order.fee, 0);
+    }
+
+    #[test]
+    fn fulfillment_stats_track_the_ewma_and_worst_latency() {
+        let mut order: OrderAccount = bytemuck::Zeroable::zeroed();
+        order.open_slot = 100;
+        order.open_timestamp = 1_000;
+
+        let mut stats = FulfillmentStats::default();
+        let clock = |slot, unix_timestamp| Clock { slot, unix_timestamp, ..Default::default() };
+
+        // The first latency seeds the average, later ones move it 1/8 of the way
+        stats.record(&order, &clock(110, 1_010));
+        assert_eq!(stats.ewma_latency_seconds, 10 * LATENCY_EWMA_SCALE);
+        stats.record(&order, &clock(126, 1_018));
+        assert_eq!(stats.ewma_latency_seconds, 11 * LATENCY_EWMA_SCALE);
+        assert_eq!(stats.ewma_latency_slots, 12 * LATENCY_EWMA_SCALE);
+        stats.record(&order, &clock(118, 1_002));
+        assert_eq!(stats.ewma_latency_seconds, 9_875);
+
+        assert_eq!(stats.fulfilled_count, 3);
+        assert_eq!(stats.total_latency_seconds, 30);
+        assert_eq!(stats.max_latency_seconds, 18);
+        assert_eq!(stats.max_latency_slots, 26);
+        assert_eq!(stats.last_fulfillment_slot, 118);
+    }
+
+    #[test]
+    fn price_deviation_bound_widens_with_time() {
+        let mut market = MarketAccount {
+            max_deviation_bps_per_second: 10,
+            ..Default::default()
+        };
+
+        // Nothing to compare against until a price is recorded
+        assert!(market.check_price_deviation(1, 1_000).is_ok());
+        market.record_price(100_000, 1_000);
+
+        // 10 bps for the minimum of one second, then 10 bps per second in either direction
+        assert!(market.check_price_deviation(100_100, 1_000).is_ok());
+        assert!(market.check_price_deviation(100_101, 1_000).is_err());
+        assert!(market.check_price_deviation(101_000, 1_010).is_ok());
+        assert!(market.check_price_deviation(98_999, 990).is_err());
+
+        // An older price doesn't replace the reference
+        market.record_price(50_000, 900);
+        assert_eq!((market.last_price, market.last_price_timestamp), (100_000, 1_000));
+
+        market.max_deviation_bps_per_second = 0;
+        assert!(market.check_price_deviation(1, 1_000).is_ok());
+    }
+
+    #[test]
+    fn migrate_converts_the_deployed_order_layout() {
+        let legacy = OrderAccountV0 {
+            open_order: 0,
+            reserved: [0; 31],
+            authority: Pubkey::new_unique(),
+            market: Pubkey::new_unique(),
+            market_name: *b"BTC\0\0\0\0\0",
+            open_timestamp: 1_000,
+            open_slot: 100,
+            close_timestamp: 1_003,
+            close_slot: 108,
+            oracle_price: 42,
+        };
+        let data = legacy.try_to_vec().unwrap();
+        // The deployed layout is 144 bytes after the discriminator with a 0 at the version offset
+        assert_eq!(data.len(), 144);
+        assert_eq!(data[ORDER_VERSION_OFFSET - 8], 0);
+
+        let mut order: OrderAccount = bytemuck::Zeroable::zeroed();
+        order.migrate(OrderAccountV0::deserialize(&mut data.as_slice()).unwrap());
+        assert_eq!(order.version, ORDER_ACCOUNT_VERSION);
+        assert_eq!(order.payer, legacy.authority);
+        assert_eq!(order.market, legacy.market);
+        assert_eq!(order.oracle_price, 42);
+        assert_eq!(order.close_slot, 108);
+        assert!(order.is_expired(1_001));
+    }
+
+    #[test]
+    fn read_legacy_account_only_reads_the_deployed_layout() {
+        let legacy = MarketAccountV0 {
+            bump: 254,
+            name: *b"ETH\0\0\0\0\0",
+            decimals: 9,
+            oracle_staleness_threshold: 30,
+        };
+        let mut data = MarketAccount::discriminator().to_vec();
+        data.extend(legacy.try_to_vec().unwrap());
+        // The deployed market was 8 + 17 bytes
+        assert_eq!(data.len(), 25);
+
+        let read = read_legacy_account::<MarketAccount, MarketAccountV0>(
+            &data,
+            8 + MarketAccountV0::INIT_SPACE
+        ).unwrap();
+        assert_eq!(read.bump, 254);
+        assert_eq!(read.name, legacy.name);
+
+        let mut migrated = Vec::new();
+        MarketAccount::default().try_serialize(&mut migrated).unwrap();
+        assert!(
+            read_legacy_account::<MarketAccount, MarketAccountV0>(
+                &migrated,
+                8 + MarketAccountV0::INIT_SPACE
+            ).is_err()
+        );
+        assert!(
+            read_legacy_account::<OracleAccount, MarketAccountV0>(
+                &data,
+                8 + MarketAccountV0::INIT_SPACE
+            ).is_err()
+        );
+    }
+
+    #[test]
+    fn price_history_rescales_to_the_new_decimals() {
+        let mut history: PriceHistoryAccount = bytemuck::Zeroable::zeroed();
+        history.push(PriceHistoryEntry {
+            timestamp: 1,
+            price: 65_000_123_456_789,
+            confidence: 2_000_000_000,
+        });
+
+        let market = MarketAccount {
+            decimals: 6,
+            ..Default::default()
+        };
+        history.rescale(&market, -9).unwrap();
+        assert_eq!(history.history()[0].price, 65_000_123_456);
+        assert_eq!(history.history()[0].confidence, 2_000_000);
+    }
 }
@@ -0,0 +1,33 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import { loadMarkets, loadProgram } from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will add a challenger that can dispute fulfilled orders."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey] = loadMarkets(program);
+
+  const challenger = process.env.CHALLENGER
+    ? new anchor.web3.PublicKey(process.env.CHALLENGER)
+    : payer.publicKey;
+
+  const txn = await program.methods
+    .addChallenger({ challenger })
+    .accounts({
+      program: programPubkey,
+      authority: payer.publicKey,
+    })
+    .rpc();
+  console.log(`[TX] add_challenger (${challenger}): ${txn}`);
+})();
@@ -0,0 +1,44 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import { loadMarkets, loadProgram } from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will add an oracle to the backfill oracle program's registry."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey] = loadMarkets(program);
+
+  // The oracle account is derived from the worker's payer
+  const oracleAuthority = process.env.ORACLE_AUTHORITY
+    ? new anchor.web3.PublicKey(process.env.ORACLE_AUTHORITY)
+    : payer.publicKey;
+  const [oracle] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("ORACLE"), oracleAuthority.toBytes()],
+    program.programId
+  );
+  // The oracle must have deposited the program's min_stake with deposit_stake
+  const [stake] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("STAKE"), oracle.toBytes()],
+    program.programId
+  );
+
+  const txn = await program.methods
+    .addOracle({ oracle })
+    .accounts({
+      program: programPubkey,
+      stake,
+      authority: payer.publicKey,
+    })
+    .rpc();
+  console.log(`[TX] add_oracle (${oracle}): ${txn}`);
+})();
@@ -0,0 +1,81 @@
+import * as anchor from "@coral-xyz/anchor";
+import dotenv from "dotenv";
+dotenv.config();
+
+// ~24 hours of slots before the oracle must heartbeat
+const ATTESTATION_VALIDITY_SLOTS = 216_000;
+
+// Number of oracle submissions required before an order settles to the median
+const ORACLE_QUORUM = Number(process.env.ORACLE_QUORUM ?? 1);
+
+// Seconds after fulfillment before anyone other than the order authority can close an order
+const ORDER_CLOSE_GRACE_PERIOD = 3600;
+
+// Seconds after fulfillment that a registered challenger can dispute an order
+const DISPUTE_WINDOW = 3600;
+
+// Lamports an oracle must stake before it can be added and submit prices
+const MIN_STAKE = 100_000_000;
+
+// Seconds an order can wait for a backfilled price before the authority can cancel it
+const ORDER_TTL = 300;
+
+// Maximum seconds between a backfilled price's publish time and the order's open time
+const MAX_PRICE_TIME_DEVIATION = 5;
+
+// Lamports escrowed by each order that needs a backfilled price to reimburse the oracles
+const ORDER_FEE = 10_000;
+
+// Orders each authority can have waiting on the oracles in a market, 0 for no limit
+const MAX_OPEN_ORDERS = 16;
+
+// Lamports escrowed by each order on top of the fee and refunded when it's closed or cancelled
+const ORDER_DEPOSIT = 1_000_000;
+
+// Basis points a backfilled price can move from the market's last fulfilled price per second
+const MAX_DEVIATION_BPS_PER_SECOND = 10;
+
+// Furthest back in seconds an order can request a historical price
+const MAX_LOOKBACK = 86_400;
+
+// Pyth devnet price accounts
+export const PYTH_BTC_PRICE_FEED = new anchor.web3.PublicKey(
+  "HovQMDrbAgAYPCmHVSrezcSmkMtXSSUsLDFANExrZh2J"
+);
+export const PYTH_ETH_PRICE_FEED = new anchor.web3.PublicKey(
+  "EdVCmQ9FSPcVe5YySXDPCRmc8aDQLKJ9xvYBMZPie1Vw"
+);
+export const PYTH_SOL_PRICE_FEED = new anchor.web3.PublicKey(
+  "J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix"
+);
+
+/** The program configs, shared by initialize and migrate_program */
+export function programConfig() {
+  return {
+    attestationValiditySlots: new anchor.BN(ATTESTATION_VALIDITY_SLOTS),
+    oracleQuorum: ORACLE_QUORUM,
+    orderCloseGracePeriod: new anchor.BN(ORDER_CLOSE_GRACE_PERIOD),
+    disputeWindow: new anchor.BN(DISPUTE_WINDOW),
+    minStake: new anchor.BN(MIN_STAKE),
+  };
+}
+
+/** A market's configs, shared by add_market and migrate_market */
+export function marketConfig(
+  name: number[],
+  pythPriceFeed: anchor.web3.PublicKey
+) {
+  return {
+    name,
+    decimals: 9,
+    oracleStalenessThreshold: 30,
+    pythPriceFeed,
+    orderTtl: ORDER_TTL,
+    maxPriceTimeDeviation: MAX_PRICE_TIME_DEVIATION,
+    orderFee: new anchor.BN(ORDER_FEE),
+    maxLookback: MAX_LOOKBACK,
+    maxOpenOrders: MAX_OPEN_ORDERS,
+    orderDeposit: new anchor.BN(ORDER_DEPOSIT),
+    maxDeviationBpsPerSecond: MAX_DEVIATION_BPS_PER_SECOND,
+  };
+}
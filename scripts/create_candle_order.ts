@@ -0,0 +1,87 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import {
+  getMarketNameBytes,
+  loadEventAuthority,
+  loadKeypair,
+  loadMarkets,
+  loadNextOrder,
+  loadProgram,
+  loadRateLimit,
+} from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+// Seconds before now the candle starts and ends, a negative end closes it in the future
+const CANDLE_START_AGO = Number(process.env.CANDLE_START_AGO ?? 300);
+const CANDLE_END_AGO = Number(process.env.CANDLE_END_AGO ?? 60);
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will create a candle order for our backfill oracle program."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey, btcMarket, ethMarket, solMarket] = loadMarkets(program);
+
+  // Use the random number in a switch statement
+  let marketString = "BTC";
+  let marketPubkey = btcMarket;
+  switch (Math.floor(Math.random() * 3)) {
+    case 0:
+      marketString = "BTC";
+      marketPubkey = btcMarket;
+      break;
+    case 1:
+      marketString = "ETH";
+      marketPubkey = ethMarket;
+      break;
+    case 2:
+      marketString = "SOL";
+      marketPubkey = solMarket;
+      break;
+    default:
+      marketString = "BTC";
+      marketPubkey = btcMarket;
+  }
+
+  const marketState = await program.account.marketAccount.fetch(marketPubkey);
+
+  const [user, order] = await loadNextOrder(
+    program,
+    marketPubkey,
+    payer.publicKey
+  );
+
+  const now = Math.floor(Date.now() / 1000);
+
+  const txn = await program.methods
+    .createCandleOrder({
+      market: getMarketNameBytes(marketString),
+      start: new anchor.BN(now - CANDLE_START_AGO),
+      end: new anchor.BN(now - CANDLE_END_AGO),
+      callback: null,
+    })
+    .accounts({
+      newOrder: {
+        program: programPubkey,
+        market: marketPubkey,
+        user,
+        order,
+        orderQueue: marketState.orderQueue,
+        rateLimit: loadRateLimit(program, marketPubkey, payer.publicKey),
+        authority: payer.publicKey,
+        payer: payer.publicKey,
+      },
+      eventAuthority: loadEventAuthority(program),
+      program: program.programId,
+    })
+    .rpc();
+  console.log(`[TX] create_candle_order (${marketString}): ${txn}`);
+})();
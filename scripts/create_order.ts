@@ -1,6 +1,14 @@
 import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
 
-import { loadKeypair, loadMarkets, loadProgram } from "./utils";
+import {
+  getMarketNameBytes,
+  loadEventAuthority,
+  loadKeypair,
+  loadMarkets,
+  loadNextOrder,
+  loadProgram,
+  loadRateLimit,
+} from "./utils";
 
 import * as anchor from "@coral-xyz/anchor";
 import chalk from "chalk";
@@ -19,43 +27,58 @@ dotenv.config();
   const [programPubkey, btcMarket, ethMarket, solMarket] = loadMarkets(program);
 
   // Use the random number in a switch statement
-  let market: any = { btc: {} };
   let marketString = "BTC";
   let marketPubkey = btcMarket;
   switch (Math.floor(Math.random() * 3)) {
     case 0:
-      market = { btc: {} };
       marketString = "BTC";
       marketPubkey = btcMarket;
       break;
     case 1:
-      market = { eth: {} };
       marketString = "ETH";
       marketPubkey = ethMarket;
       break;
     case 2:
-      market = { sol: {} };
       marketString = "SOL";
       marketPubkey = solMarket;
       break;
     default:
-      market = { btc: {} };
       marketString = "BTC";
       marketPubkey = btcMarket;
   }
 
-  const keypair = anchor.web3.Keypair.generate();
+  const marketState = await program.account.marketAccount.fetch(marketPubkey);
+
+  const [user, order] = await loadNextOrder(
+    program,
+    marketPubkey,
+    payer.publicKey
+  );
 
   const txn = await program.methods
-    .createOrder({ market })
+    .createOrder({
+      market: getMarketNameBytes(marketString),
+      timestamp: process.env.ORDER_TIMESTAMP
+        ? new anchor.BN(process.env.ORDER_TIMESTAMP)
+        : null,
+      kind: { price: {} },
+      callback: null,
+    })
     .accounts({
-      order: keypair.publicKey,
-      program: programPubkey,
-      market: marketPubkey,
-      authority: payer.publicKey,
-      payer: payer.publicKey,
+      newOrder: {
+        program: programPubkey,
+        market: marketPubkey,
+        user,
+        order,
+        orderQueue: marketState.orderQueue,
+        rateLimit: loadRateLimit(program, marketPubkey, payer.publicKey),
+        authority: payer.publicKey,
+        payer: payer.publicKey,
+      },
+      pythPrice: marketState.pythPriceFeed,
+      eventAuthority: loadEventAuthority(program),
+      program: program.programId,
     })
-    .signers([keypair])
     .rpc();
   console.log(`[TX] create_order (${marketString}): ${txn}`);
 })();
@@ -0,0 +1,82 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import {
+  getMarketNameBytes,
+  loadEventAuthority,
+  loadKeypair,
+  loadMarkets,
+  loadNextOrder,
+  loadProgram,
+  loadRateLimit,
+} from "./utils";
+
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+// Seconds to average the price over
+const TWAP_WINDOW = Number(process.env.TWAP_WINDOW ?? 60);
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will create a TWAP order for our backfill oracle program."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey, btcMarket, ethMarket, solMarket] = loadMarkets(program);
+
+  // Use the random number in a switch statement
+  let marketString = "BTC";
+  let marketPubkey = btcMarket;
+  switch (Math.floor(Math.random() * 3)) {
+    case 0:
+      marketString = "BTC";
+      marketPubkey = btcMarket;
+      break;
+    case 1:
+      marketString = "ETH";
+      marketPubkey = ethMarket;
+      break;
+    case 2:
+      marketString = "SOL";
+      marketPubkey = solMarket;
+      break;
+    default:
+      marketString = "BTC";
+      marketPubkey = btcMarket;
+  }
+
+  const marketState = await program.account.marketAccount.fetch(marketPubkey);
+
+  const [user, order] = await loadNextOrder(
+    program,
+    marketPubkey,
+    payer.publicKey
+  );
+
+  const txn = await program.methods
+    .createTwapOrder({
+      market: getMarketNameBytes(marketString),
+      window: TWAP_WINDOW,
+      callback: null,
+    })
+    .accounts({
+      newOrder: {
+        program: programPubkey,
+        market: marketPubkey,
+        user,
+        order,
+        orderQueue: marketState.orderQueue,
+        rateLimit: loadRateLimit(program, marketPubkey, payer.publicKey),
+        authority: payer.publicKey,
+        payer: payer.publicKey,
+      },
+      eventAuthority: loadEventAuthority(program),
+      program: program.programId,
+    })
+    .rpc();
+  console.log(`[TX] create_twap_order (${marketString}): ${txn}`);
+})();
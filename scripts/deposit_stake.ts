@@ -0,0 +1,48 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import { loadMarkets, loadProgram } from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will deposit stake for the oracle owned by your wallet."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey] = loadMarkets(program);
+  const programState = await program.account.programAccount.fetch(
+    programPubkey
+  );
+
+  // The oracle account is derived from the worker's payer, which must be your wallet
+  const [oracle] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("ORACLE"), payer.publicKey.toBytes()],
+    program.programId
+  );
+  const [stake] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("STAKE"), oracle.toBytes()],
+    program.programId
+  );
+
+  const amount = process.env.STAKE_AMOUNT
+    ? new anchor.BN(process.env.STAKE_AMOUNT)
+    : programState.minStake;
+
+  const txn = await program.methods
+    .depositStake({ amount })
+    .accounts({
+      oracle,
+      stake,
+      authority: payer.publicKey,
+      payer: payer.publicKey,
+    })
+    .rpc();
+  console.log(`[TX] deposit_stake (${oracle}): ${txn}`);
+})();
@@ -0,0 +1,53 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import { getMarketNameBytes, loadMarkets, loadProgram } from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+// Furthest in seconds the recorded price can be before the timestamp
+const MAX_AGE = Number(process.env.MAX_AGE ?? 60);
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will read a recorded price from a market's price history."
+    )}`
+  );
+
+  const [program] = loadProgram();
+
+  const [programPubkey, btcMarket, ethMarket, solMarket] = loadMarkets(program);
+
+  const marketString = process.env.MARKET ?? "BTC";
+  const marketPubkey =
+    marketString === "ETH"
+      ? ethMarket
+      : marketString === "SOL"
+      ? solMarket
+      : btcMarket;
+
+  const marketState = await program.account.marketAccount.fetch(marketPubkey);
+
+  const timestamp = process.env.TIMESTAMP
+    ? Number(process.env.TIMESTAMP)
+    : Math.floor(Date.now() / 1000);
+
+  const entry = await program.methods
+    .getHistoricalPrice({
+      market: getMarketNameBytes(marketString),
+      timestamp: new anchor.BN(timestamp),
+      maxAge: MAX_AGE,
+    })
+    .accounts({
+      program: programPubkey,
+      market: marketPubkey,
+      priceHistory: marketState.priceHistory,
+    })
+    .view();
+  console.log(
+    `[${marketString}] ${entry.timestamp.toString()}: ${entry.price.toString()} +/- ${entry.confidence.toString()}`
+  );
+})();
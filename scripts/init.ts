@@ -1,13 +1,22 @@
 import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
 
+import {
+  marketConfig,
+  programConfig,
+  PYTH_BTC_PRICE_FEED,
+  PYTH_ETH_PRICE_FEED,
+  PYTH_SOL_PRICE_FEED,
+} from "./config";
 import {
   getMarketNameBytes,
   loadKeypair,
   loadMarkets,
+  loadOrderQueue,
+  loadPriceHistory,
   loadProgram,
 } from "./utils";
 
-import type * as anchor from "@coral-xyz/anchor";
+import * as anchor from "@coral-xyz/anchor";
 import chalk from "chalk";
 import dotenv from "dotenv";
 dotenv.config();
@@ -45,17 +54,33 @@ dotenv.config();
   );
 
   const txn = await program.methods
-    .initialize()
+    .initialize(programConfig())
     .accounts({
       program: programPubkey,
-      btcMarket,
-      ethMarket,
-      solMarket,
       authority: payer.publicKey,
       payer: payer.publicKey,
     })
     .rpc();
   console.log(`[TX] initialize: ${txn}`);
+
+  for (const [name, market, pythPriceFeed] of [
+    ["BTC", btcMarket, PYTH_BTC_PRICE_FEED],
+    ["ETH", ethMarket, PYTH_ETH_PRICE_FEED],
+    ["SOL", solMarket, PYTH_SOL_PRICE_FEED],
+  ] as const) {
+    const addMarketTxn = await program.methods
+      .addMarket(marketConfig(getMarketNameBytes(name), pythPriceFeed))
+      .accounts({
+        program: programPubkey,
+        market,
+        orderQueue: loadOrderQueue(program, market),
+        priceHistory: loadPriceHistory(program, market),
+        authority: payer.publicKey,
+        payer: payer.publicKey,
+      })
+      .rpc();
+    console.log(`[TX] add_market (${name}): ${addMarketTxn}`);
+  }
 })();
 
 export async function verifyAccountDoesntExist(
@@ -11,6 +11,12 @@ import fs from "fs";
 dotenv.config();
 
 const ORDER_ACCOUNT_DISCRIMINATOR: string = "EFxNNV2jHAE";
+// Legacy orders have a 0 at the version offset and can't be decoded until they're migrated
+const ORDER_VERSION_OFFSET = 9;
+const ORDER_ACCOUNT_VERSION = 1;
+
+// The on-chain exponentially weighted averages are scaled up by this
+const LATENCY_EWMA_SCALE = 1_000;
 
 interface IMetric {
   openTimestamp: number;
@@ -35,7 +41,17 @@ interface IMetric {
 
   const accounts = await program.provider.connection.getProgramAccounts(
     program.programId,
-    { filters: [{ memcmp: { offset: 0, bytes: ORDER_ACCOUNT_DISCRIMINATOR } }] }
+    {
+      filters: [
+        { memcmp: { offset: 0, bytes: ORDER_ACCOUNT_DISCRIMINATOR } },
+        {
+          memcmp: {
+            offset: ORDER_VERSION_OFFSET,
+            bytes: bs58.encode([ORDER_ACCOUNT_VERSION]),
+          },
+        },
+      ],
+    }
   );
 
   const metrics: IMetric[] = [];
@@ -138,17 +154,100 @@ interface IMetric {
   fs.writeFileSync(fileName, fileString, { encoding: "utf-8" });
 
   console.log(`Metrics saved to './${fileName}'`);
+
+  // The running stats each market keeps on chain for the orders the oracles settled
+  for (const [name, marketPubkey] of [
+    ["BTC", btcMarket],
+    ["ETH", ethMarket],
+    ["SOL", solMarket],
+  ] as const) {
+    const { stats } = await program.account.marketAccount.fetch(marketPubkey);
+    console.log(
+      `\n[${name}] Fulfilled Orders: ${chalk.green(
+        stats.fulfilledCount.toString()
+      )}\nEWMA Latency (seconds): ${chalk.green(
+        stats.ewmaLatencySeconds.toNumber() / LATENCY_EWMA_SCALE
+      )}\nEWMA Latency (slots): ${chalk.green(
+        stats.ewmaLatencySlots.toNumber() / LATENCY_EWMA_SCALE
+      )}\nMaximum Latency (seconds): ${chalk.green(
+        stats.maxLatencySeconds.toString()
+      )}\nLast Fulfillment Slot: ${chalk.green(
+        stats.lastFulfillmentSlot.toString()
+      )}`
+    );
+  }
 })();
 
+interface Ohlc {
+  open: anchor.BN;
+  high: anchor.BN;
+  low: anchor.BN;
+  close: anchor.BN;
+}
+
 interface OrderAccount {
   openOrder: number;
+  version: number;
+  bump: number;
+  quorum: number;
+  numSources: number;
+  sourceBitmap: number;
+  numSubmissions: number;
+  kind: number;
+  side: number;
+  fillStatus: number;
+  hasCandle: number;
+  hasCallback: number;
   reserved: number[];
   authority: anchor.web3.PublicKey;
   market: anchor.web3.PublicKey;
+  payer: anchor.web3.PublicKey;
+  nonce: anchor.BN;
   marketName: number[];
   openTimestamp: anchor.BN;
   openSlot: anchor.BN;
+  requestedTimestamp: anchor.BN;
+  expiryTimestamp: anchor.BN;
   closeTimestamp: anchor.BN;
   closeSlot: anchor.BN;
   oraclePrice: anchor.BN;
+  pricePublishTime: anchor.BN;
+  priceConfidence: anchor.BN;
+  candleEnd: anchor.BN;
+  fee: anchor.BN;
+  numSamples: number;
+  twapWindow: number;
+  kindPrice: anchor.BN;
+  candle: Ohlc;
+  callback: {
+    programId: anchor.web3.PublicKey;
+    discriminator: number[];
+    numAccounts: number;
+    reserved: number[];
+    accounts: { pubkey: anchor.web3.PublicKey; isWritable: number }[];
+  };
+  dispute: {
+    status: number;
+    reserved: number[];
+    challenger: anchor.web3.PublicKey;
+    oracle: anchor.web3.PublicKey;
+    price: anchor.BN;
+    publishTime: anchor.BN;
+    evidence: number[];
+    timestamp: anchor.BN;
+    slashedAmount: anchor.BN;
+  };
+  // Only the first numSubmissions entries are set
+  submissions: {
+    oracle: anchor.web3.PublicKey;
+    price: anchor.BN;
+    publishTime: anchor.BN;
+    confidence: anchor.BN;
+    candle: Ohlc;
+    numSamples: number;
+    numSources: number;
+    sourceBitmap: number;
+    hasCandle: number;
+    overrideDeviation: number;
+  }[];
 }
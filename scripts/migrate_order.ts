@@ -0,0 +1,54 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import { loadProgram } from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import { bs58 } from "@coral-xyz/anchor/dist/cjs/utils/bytes";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+const ORDER_ACCOUNT_DISCRIMINATOR: string = "EFxNNV2jHAE";
+const ORDER_VERSION_OFFSET = 9;
+const ORDER_AUTHORITY_OFFSET = 40;
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will migrate legacy order accounts to the zero-copy layout."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  // Only migrate the orders of one authority if ORDER_AUTHORITY is set
+  const filters: anchor.web3.GetProgramAccountsFilter[] = [
+    { memcmp: { offset: 0, bytes: ORDER_ACCOUNT_DISCRIMINATOR } },
+    { memcmp: { offset: ORDER_VERSION_OFFSET, bytes: bs58.encode([0]) } },
+  ];
+  if (process.env.ORDER_AUTHORITY) {
+    filters.push({
+      memcmp: {
+        offset: ORDER_AUTHORITY_OFFSET,
+        bytes: new anchor.web3.PublicKey(process.env.ORDER_AUTHORITY).toBase58(),
+      },
+    });
+  }
+
+  const accounts = await program.provider.connection.getProgramAccounts(
+    program.programId,
+    { filters, dataSlice: { offset: 0, length: 0 } }
+  );
+  console.log(`Found ${accounts.length} legacy orders`);
+
+  for (const { pubkey } of accounts) {
+    const txn = await program.methods
+      .migrateOrder()
+      .accounts({
+        order: pubkey,
+        payer: payer.publicKey,
+      })
+      .rpc();
+    console.log(`[TX] migrate_order (${pubkey}): ${txn}`);
+  }
+})();
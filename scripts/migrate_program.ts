@@ -0,0 +1,96 @@
+import type { BackfillOracleProgram } from "../target/types/backfill_oracle_program";
+
+import {
+  marketConfig,
+  programConfig,
+  PYTH_BTC_PRICE_FEED,
+  PYTH_ETH_PRICE_FEED,
+  PYTH_SOL_PRICE_FEED,
+} from "./config";
+import {
+  getMarketNameBytes,
+  loadMarkets,
+  loadOrderQueue,
+  loadPriceHistory,
+  loadProgram,
+} from "./utils";
+
+import * as anchor from "@coral-xyz/anchor";
+import { bs58 } from "@coral-xyz/anchor/dist/cjs/utils/bytes";
+import chalk from "chalk";
+import dotenv from "dotenv";
+dotenv.config();
+
+// The originally deployed oracle account was 8 + 89 bytes
+const LEGACY_ORACLE_ACCOUNT_SIZE = 97;
+
+(async () => {
+  console.log(
+    `\n${chalk.green(
+      "This script will migrate the program, market and oracle accounts of the originally deployed program to the current layout."
+    )}`
+  );
+
+  const [program, payer] = loadProgram();
+
+  const [programPubkey, btcMarket, ethMarket, solMarket] = loadMarkets(program);
+
+  const txn = await program.methods
+    .migrateProgram(programConfig())
+    .accounts({
+      program: programPubkey,
+      authority: payer.publicKey,
+      payer: payer.publicKey,
+    })
+    .rpc();
+  console.log(`[TX] migrate_program: ${txn}`);
+
+  for (const [name, market, pythPriceFeed] of [
+    ["BTC", btcMarket, PYTH_BTC_PRICE_FEED],
+    ["ETH", ethMarket, PYTH_ETH_PRICE_FEED],
+    ["SOL", solMarket, PYTH_SOL_PRICE_FEED],
+  ] as const) {
+    const migrateMarketTxn = await program.methods
+      .migrateMarket(marketConfig(getMarketNameBytes(name), pythPriceFeed))
+      .accounts({
+        program: programPubkey,
+        market,
+        orderQueue: loadOrderQueue(program, market),
+        priceHistory: loadPriceHistory(program, market),
+        authority: payer.publicKey,
+        payer: payer.publicKey,
+      })
+      .rpc();
+    console.log(`[TX] migrate_market (${name}): ${migrateMarketTxn}`);
+  }
+
+  const oracles = await program.provider.connection.getProgramAccounts(
+    program.programId,
+    {
+      filters: [
+        { dataSize: LEGACY_ORACLE_ACCOUNT_SIZE },
+        {
+          memcmp: {
+            offset: 0,
+            bytes: bs58.encode(
+              anchor.BorshAccountsCoder.accountDiscriminator("OracleAccount")
+            ),
+          },
+        },
+      ],
+      dataSlice: { offset: 0, length: 0 },
+    }
+  );
+  console.log(`Found ${oracles.length} legacy oracles`);
+
+  for (const { pubkey } of oracles) {
+    const migrateOracleTxn = await program.methods
+      .migrateOracle()
+      .accounts({
+        oracle: pubkey,
+        payer: payer.publicKey,
+      })
+      .rpc();
+    console.log(`[TX] migrate_oracle (${pubkey}): ${migrateOracleTxn}`);
+  }
+})();
@@ -57,6 +57,75 @@ export function loadMarkets(
   return [programPubkey, btcMarket, ethMarket, solMarket];
 }
 
+/** Derive the ring buffer of open orders for a market */
+export function loadOrderQueue(
+  program: anchor.Program<BackfillOracleProgram>,
+  market: anchor.web3.PublicKey
+): anchor.web3.PublicKey {
+  const [orderQueue] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("QUEUE"), market.toBytes()],
+    program.programId
+  );
+  return orderQueue;
+}
+
+/** Derive the PDA that signs the program's self-CPI events */
+export function loadEventAuthority(
+  program: anchor.Program<BackfillOracleProgram>
+): anchor.web3.PublicKey {
+  const [eventAuthority] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("__event_authority")],
+    program.programId
+  );
+  return eventAuthority;
+}
+
+/** Derive the ring buffer of recently fulfilled prices for a market */
+export function loadPriceHistory(
+  program: anchor.Program<BackfillOracleProgram>,
+  market: anchor.web3.PublicKey
+): anchor.web3.PublicKey {
+  const [priceHistory] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("HISTORY"), market.toBytes()],
+    program.programId
+  );
+  return priceHistory;
+}
+
+/** Derive the account tracking an authority's open orders in a market */
+export function loadRateLimit(
+  program: anchor.Program<BackfillOracleProgram>,
+  market: anchor.web3.PublicKey,
+  authority: anchor.web3.PublicKey
+): anchor.web3.PublicKey {
+  const [rateLimit] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("RATE_LIMIT"), market.toBytes(), authority.toBytes()],
+    program.programId
+  );
+  return rateLimit;
+}
+
+/** Derive the authority's user account and the address of its next order in a market */
+export async function loadNextOrder(
+  program: anchor.Program<BackfillOracleProgram>,
+  market: anchor.web3.PublicKey,
+  authority: anchor.web3.PublicKey
+): Promise<[anchor.web3.PublicKey, anchor.web3.PublicKey]> {
+  const [user] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("USER"), authority.toBytes()],
+    program.programId
+  );
+  const userState = await program.account.userAccount.fetchNullable(user);
+  const nonce = userState ? userState.nonce : new anchor.BN(0);
+
+  const [order] = anchor.web3.PublicKey.findProgramAddressSync(
+    [market.toBytes(), authority.toBytes(), nonce.toArrayLike(Buffer, "le", 8)],
+    program.programId
+  );
+
+  return [user, order];
+}
+
 export function loadKeypair(keypairPath: string): anchor.web3.Keypair {
   const fullPath =
     keypairPath.startsWith("/") || keypairPath.startsWith("C:")
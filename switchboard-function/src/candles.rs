@@ -0,0 +1,60 @@
+use crate::*;
+
+/// Builds an OHLC candle from a time ordered series of prices. Point prices are folded in as
+/// single price candles and the per-second candles built from ticks are merged whole.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CandleAggregator {
+    candle: Option<Ohlc>,
+    /// Number of prices or candles merged into the candle
+    pub num_samples: u32,
+}
+impl CandleAggregator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_price(&mut self, price: u64) {
+        self.add_candle(point_candle(price));
+    }
+
+    pub fn add_candle(&mut self, candle: Ohlc) {
+        self.candle = Some(match self.candle {
+            Some(current) =>
+                Ohlc {
+                    open: current.open,
+                    high: std::cmp::max(current.high, candle.high),
+                    low: std::cmp::min(current.low, candle.low),
+                    close: candle.close,
+                },
+            None => candle,
+        });
+        self.num_samples += 1;
+    }
+
+    pub fn candle(&self) -> Option<Ohlc> {
+        self.candle
+    }
+}
+
+/// A candle that opens, closes and trades at a single price
+pub fn point_candle(price: u64) -> Ohlc {
+    Ohlc {
+        open: price,
+        high: price,
+        low: price,
+        close: price,
+    }
+}
+
+/// Average two candles component-wise. The high and low still bound the open and close since
+/// they do in both candles.
+pub fn average_candles(a: &Ohlc, b: &Ohlc) -> Ohlc {
+    let average = |x: u64, y: u64| (((x as u128) + (y as u128)) / 2) as u64;
+
+    Ohlc {
+        open: average(a.open, b.open),
+        high: average(a.high, b.high),
+        low: average(a.low, b.low),
+        close: average(a.close, b.close),
+    }
+}
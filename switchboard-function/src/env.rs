@@ -31,6 +31,18 @@ pub struct WorkerEnvironment {
     pub program_id: String,
     #[serde(default = "default_pyth_rpc_url")]
     pub pyth_rpc_url: String,
+    /// Periodically close fulfilled orders once their grace period has elapsed
+    #[serde(default)]
+    pub sweep_fulfilled_orders: bool,
+    /// Comma-separated orders whose price can be resubmitted beyond the market's deviation bound,
+    /// e.g. to recover after a real gap in the market. An order is only resubmitted with the
+    /// override flag after the program rejected its price with PriceDeviationExceeded.
+    #[serde(default)]
+    pub override_price_deviation_orders: Vec<String>,
+    /// Comma-separated market names whose orders can be resubmitted with the override flag, the
+    /// same as listing each of their orders in OVERRIDE_PRICE_DEVIATION_ORDERS
+    #[serde(default)]
+    pub override_price_deviation_markets: Vec<String>,
 }
 impl WorkerEnvironment {
     pub fn get_or_init() -> &'static Self {
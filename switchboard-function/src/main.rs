@@ -20,6 +20,9 @@ pub use env::*;
 pub mod utils;
 pub use utils::*;
 
+pub mod candles;
+pub use candles::*;
+
 pub use futures::{ Future, StreamExt };
 pub use std::sync::Arc;
 pub use std::str::FromStr;
@@ -35,13 +38,26 @@ pub use backfill_oracle_program::{
     ID as ProgramID,
     MarketAccount,
     ProgramAccount,
+    ProgramError as BackfillOracleError,
     OracleAccount,
     OrderAccount,
+    OrderQueueAccount,
+    OrderCallback,
+    DisputeStatus,
+    Ohlc,
     OraclePriceFulfilledEvent,
     OraclePriceRequestedEvent,
-    MarketType,
     RegisterOracle,
     FulfillOrderParams,
+    FulfillOrdersParams,
+    SOURCE_PYTH,
+    SOURCE_COINBASE,
+    EVENT_AUTHORITY_SEED,
+    CALLBACK_AUTHORITY_SEED,
+    ORDER_ACCOUNT_VERSION,
+    ORDER_OPEN_OFFSET,
+    ORDER_VERSION_OFFSET,
+    ORDER_MARKET_OFFSET,
 };
 
 pub use miette::Result;
@@ -57,6 +57,10 @@ pub struct CoinbaseProvider {
     pub btc: Arc<Cache<i64, u64>>,
     pub eth: Arc<Cache<i64, u64>>,
     pub sol: Arc<Cache<i64, u64>>,
+    /// The open, high, low and close of the ticks within each second
+    pub btc_candles: Arc<Cache<i64, Ohlc>>,
+    pub eth_candles: Arc<Cache<i64, Ohlc>>,
+    pub sol_candles: Arc<Cache<i64, Ohlc>>,
 }
 impl std::default::Default for CoinbaseProvider {
     fn default() -> Self {
@@ -64,6 +68,9 @@ impl std::default::Default for CoinbaseProvider {
             btc: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
             eth: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
             sol: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
+            btc_candles: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
+            eth_candles: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
+            sol_candles: Arc::new(Cache::new(Some(Duration::from_secs(3600)))),
         }
     }
 }
@@ -107,10 +114,10 @@ impl CoinbaseProvider {
 
         while let Some(data) = rx.recv().await {
             if let Ok(ticker) = serde_json::from_str::<CoinbaseTickerMessage>(&data) {
-                let (cache, temp_store) = match ticker.product_id.as_str() {
-                    "BTC-USD" => (self.btc.clone(), btc_temp_store.clone()),
-                    "ETH-USD" => (self.eth.clone(), eth_temp_store.clone()),
-                    "SOL-USD" => (self.sol.clone(), sol_temp_store.clone()),
+                let (cache, candles, temp_store) = match ticker.product_id.as_str() {
+                    "BTC-USD" => (self.btc.clone(), self.btc_candles.clone(), btc_temp_store.clone()),
+                    "ETH-USD" => (self.eth.clone(), self.eth_candles.clone(), eth_temp_store.clone()),
+                    "SOL-USD" => (self.sol.clone(), self.sol_candles.clone(), sol_temp_store.clone()),
                     _ => {
                         continue;
                     }
@@ -136,6 +143,20 @@ impl CoinbaseProvider {
                         };
 
                         cache.set(timestamp, avg_price, None);
+
+                        // Ticks arrive in order so the latest tick closes the second
+                        let candle = match candles.get(&timestamp) {
+                            Some(candle) =>
+                                Ohlc {
+                                    high: std::cmp::max(candle.high, price),
+                                    low: std::cmp::min(candle.low, price),
+                                    close: price,
+                                    ..candle
+                                },
+                            None => point_candle(price),
+                        };
+                        candles.set(timestamp, candle, None);
+
                         debug!("[COINBASE] {}: {} => {}", ticker.product_id, timestamp, avg_price);
                     }
                 };
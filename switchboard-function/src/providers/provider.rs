@@ -1,5 +1,59 @@
+use crate::Ohlc;
 use async_trait::async_trait;
 
+/// A price resolved from a data provider, fixed to 9 decimal places
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderPrice {
+    pub price: u64,
+    pub confidence: u64,
+    /// The source's publish time for the price
+    pub publish_time: i64,
+    /// A bitmap of the SOURCE_* providers that contributed to the price
+    pub source_bitmap: u8,
+    /// Number of per-second prices averaged into the price, 1 for a point price
+    pub num_samples: u32,
+    /// The candle for a candle order, its close is the price
+    pub candle: Option<Ohlc>,
+}
+/// Decimal places of the prices resolved from the data providers
+pub const PROVIDER_PRICE_DECIMALS: u32 = 9;
+
+impl ProviderPrice {
+    pub fn num_sources(&self) -> u8 {
+        self.source_bitmap.count_ones() as u8
+    }
+
+    /// Rescale the price, confidence and candle to a market's decimals, which the program settles
+    /// and compares the market's prices in
+    pub fn scale_to_decimals(&self, decimals: u32) -> Option<ProviderPrice> {
+        let scale = |value: u64| -> Option<u64> {
+            if decimals >= PROVIDER_PRICE_DECIMALS {
+                value.checked_mul(10u64.checked_pow(decimals - PROVIDER_PRICE_DECIMALS)?)
+            } else {
+                Some(value / 10u64.checked_pow(PROVIDER_PRICE_DECIMALS - decimals)?)
+            }
+        };
+
+        let candle = match self.candle {
+            Some(candle) =>
+                Some(Ohlc {
+                    open: scale(candle.open)?,
+                    high: scale(candle.high)?,
+                    low: scale(candle.low)?,
+                    close: scale(candle.close)?,
+                }),
+            None => None,
+        };
+
+        Some(ProviderPrice {
+            price: scale(self.price)?,
+            confidence: scale(self.confidence)?,
+            candle,
+            ..*self
+        })
+    }
+}
+
 #[async_trait]
 pub trait DataProvider {
     async fn watch(&self, routine_interval: Option<u64>);
@@ -24,17 +24,36 @@ pub struct PythPriceInfo {
 }
 impl PythPriceInfo {
     pub fn to_fixed_precision(&self) -> u64 {
-        let expo_diff = 9 - self.expo.abs(); // calculate the difference in precision
-        let price = u64::from_str(&self.price).unwrap();
-        if self.expo < 0 {
-            price * (10u64).pow(expo_diff as u32)
-        } else {
-            price / (10u64).pow(expo_diff as u32)
+        scale_to_fixed_precision(&self.price, self.expo)
+    }
+
+    pub fn conf_to_fixed_precision(&self) -> u64 {
+        scale_to_fixed_precision(&self.conf, self.expo)
+    }
+
+    pub fn to_provider_price(&self) -> ProviderPrice {
+        ProviderPrice {
+            price: self.to_fixed_precision(),
+            confidence: self.conf_to_fixed_precision(),
+            publish_time: self.publish_time.try_into().unwrap(),
+            source_bitmap: SOURCE_PYTH,
+            num_samples: 1,
+            candle: None,
         }
     }
 }
 
-async fn fetch_pyth_price(id: &str, timestamp: i64) -> Result<u64, SbError> {
+fn scale_to_fixed_precision(value: &str, expo: i32) -> u64 {
+    let expo_diff = (PROVIDER_PRICE_DECIMALS as i32) - expo.abs(); // calculate the difference in precision
+    let value = u64::from_str(value).unwrap();
+    if expo < 0 {
+        value * (10u64).pow(expo_diff as u32)
+    } else {
+        value / (10u64).pow(expo_diff as u32)
+    }
+}
+
+async fn fetch_pyth_price(id: &str, timestamp: i64) -> Result<ProviderPrice, SbError> {
     let env = WorkerEnvironment::get_or_init();
     let url = format!(
         "{}/api/get_price_feed?id={}&publish_time={}",
@@ -47,7 +66,7 @@ async fn fetch_pyth_price(id: &str, timestamp: i64) -> Result<u64, SbError> {
 
     if response.status().is_success() {
         let api_response: PythApiResponse = response.json().await.unwrap();
-        let result = api_response.price.to_fixed_precision();
+        let result = api_response.price.to_provider_price();
         Ok(result)
     } else {
         error!("[PYTH] Failed to fetch data: {}", response.status());
@@ -80,9 +99,9 @@ async fn fetch_pyth_prices(ids: Vec<&str>) -> Result<Vec<PythApiResponse>, SbErr
 
 #[derive(Clone)]
 pub struct PythProvider {
-    pub btc: TimestampCache<u64>,
-    pub eth: TimestampCache<u64>,
-    pub sol: TimestampCache<u64>,
+    pub btc: TimestampCache<ProviderPrice>,
+    pub eth: TimestampCache<ProviderPrice>,
+    pub sol: TimestampCache<ProviderPrice>,
 }
 impl std::default::Default for PythProvider {
     fn default() -> Self {
@@ -109,7 +128,7 @@ impl PythProvider {
 
         for price in prices {
             let timestamp: i64 = price.price.publish_time.try_into().unwrap();
-            let value: u64 = price.price.to_fixed_precision();
+            let value: ProviderPrice = price.price.to_provider_price();
 
             match price.id.as_str() {
                 PYTH_BTC_FEED => {
@@ -1,6 +1,64 @@
+use crate::*;
+
 #[derive(Default, Clone, Debug)]
 pub enum WorkerStatus {
     #[default]
     Initializing,
     Ready,
 }
+
+pub static BTC_MARKET_BYTES: [u8; 8] = [66, 84, 67, 0, 0, 0, 0, 0];
+pub static ETH_MARKET_BYTES: [u8; 8] = [69, 84, 72, 0, 0, 0, 0, 0];
+pub static SOL_MARKET_BYTES: [u8; 8] = [83, 79, 76, 0, 0, 0, 0, 0];
+
+/// The markets the worker has price providers for. The program accepts any 8-byte market name
+/// but the worker can only fulfill orders for markets it knows how to price, the markets listed
+/// on chain are loaded from the program account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MarketType {
+    Btc,
+    Eth,
+    Sol,
+}
+impl MarketType {
+    pub fn to_bytes(&self) -> [u8; 8] {
+        match &self {
+            MarketType::Btc => BTC_MARKET_BYTES,
+            MarketType::Eth => ETH_MARKET_BYTES,
+            MarketType::Sol => SOL_MARKET_BYTES,
+        }
+    }
+}
+impl std::str::FromStr for MarketType {
+    type Err = SbError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "BTC" => Ok(MarketType::Btc),
+            "ETH" => Ok(MarketType::Eth),
+            "SOL" => Ok(MarketType::Sol),
+            _ => Err(SbError::Message("InvalidMarketName")),
+        }
+    }
+}
+/// A market's 8-byte name as a string, without its trailing zero padding
+pub fn market_name(bytes: &[u8; 8]) -> String {
+    let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
+
+impl TryFrom<[u8; 8]> for MarketType {
+    type Error = SbError;
+
+    fn try_from(bytes: [u8; 8]) -> std::result::Result<Self, Self::Error> {
+        if bytes == BTC_MARKET_BYTES {
+            Ok(MarketType::Btc)
+        } else if bytes == ETH_MARKET_BYTES {
+            Ok(MarketType::Eth)
+        } else if bytes == SOL_MARKET_BYTES {
+            Ok(MarketType::Sol)
+        } else {
+            Err(SbError::Message("InvalidMarketName"))
+        }
+    }
+}
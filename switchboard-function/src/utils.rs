@@ -11,6 +11,19 @@ pub fn get_market_name_bytes(s: &str) -> [u8; 8] {
     b
 }
 
+/// Deserialize an order account in the current layout. Legacy orders are shorter than the
+/// zero-copy layout so they're skipped until they're migrated.
+pub fn parse_order(data: &[u8]) -> Option<OrderAccount> {
+    if
+        data.len() != 8 + std::mem::size_of::<OrderAccount>() ||
+        data[ORDER_VERSION_OFFSET] != ORDER_ACCOUNT_VERSION
+    {
+        return None;
+    }
+
+    OrderAccount::try_deserialize(&mut &data[..]).ok()
+}
+
 pub async fn start_routine<F, Fut>(routine_interval: u64, async_fn: F) -> Result<(), SbError>
     where F: FnMut() -> Fut, Fut: std::future::Future<Output = Result<(), SbError>>
 {
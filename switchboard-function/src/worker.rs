@@ -1,6 +1,5 @@
 use crate::*;
 
-use base64::{ engine::general_purpose, Engine as _ };
 use switchboard_solana::{
     solana_client::{
         nonblocking::pubsub_client::PubsubClient,
@@ -9,16 +8,50 @@ use switchboard_solana::{
             RpcTransactionLogsConfig,
             RpcProgramAccountsConfig,
             RpcAccountInfoConfig,
+            RpcTransactionConfig,
         },
     },
     solana_sdk::commitment_config::CommitmentConfig,
     get_ixn_discriminator,
 };
+use solana_sdk::signature::Signature;
+use solana_transaction_status::{ option_serializer::OptionSerializer, UiInstruction, UiTransactionEncoding };
 use std::ops::Deref;
 use solana_program::hash::Hash;
 use anchor_lang::Discriminator;
 use futures::future::join_all;
 use std::time::Duration;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_client::rpc_filter::{ Memcmp, RpcFilterType };
+
+/// Estimated compute units for the fulfill_orders instruction overhead and each order in the batch
+const FULFILL_ORDERS_BASE_COMPUTE_UNITS: u32 = 20_000;
+const FULFILL_ORDERS_COMPUTE_UNITS_PER_ORDER: u32 = 40_000;
+const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Leave room for the callback program when fulfilling a single order
+const FULFILL_ORDER_CALLBACK_COMPUTE_UNITS: u32 = 400_000;
+const MAX_ORDERS_PER_COMPUTE_BUDGET: usize = ((MAX_COMPUTE_UNITS -
+    FULFILL_ORDERS_BASE_COMPUTE_UNITS) /
+    FULFILL_ORDERS_COMPUTE_UNITS_PER_ORDER) as usize;
+
+/// Withdraw the oracle's earned order fees once the payer balance drops below this many lamports
+const WITHDRAW_FEES_BALANCE_THRESHOLD: u64 = 500_000_000;
+
+/// Maximum number of accounts the RPC returns from a single getMultipleAccounts call
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Number of close_order instructions to pack into a single sweep transaction
+const MAX_CLOSE_ORDERS_PER_TXN: usize = 8;
+
+/// Most per-second prices fetched at once for a TWAP window or candle interval, so a cold cache
+/// doesn't fire thousands of Pyth benchmark requests at the same time
+const MAX_CONCURRENT_PRICE_FETCHES: usize = 16;
+
+/// Attempts at fetching a fulfill_orders transaction's logs before giving up, since it was only
+/// confirmed at the processed commitment
+const MAX_FETCH_TXN_ATTEMPTS: usize = 10;
 
 pub struct OracleWorker {
     pub status: WorkerStatus,
@@ -33,7 +66,13 @@ pub struct OracleWorker {
 
     pub program_state_pubkey: Pubkey,
     pub oracle_pubkey: Pubkey,
+    pub stake_pubkey: Pubkey,
+    pub event_authority_pubkey: Pubkey,
     pub enclave_signer: Arc<Keypair>,
+    pub attestation_validity_slots: u64,
+    pub order_close_grace_period: i64,
+    pub dispute_window: i64,
+    pub oracle_valid_until_slot: Arc<RwLock<u64>>,
 
     pub payer_balance: Arc<RwLock<u64>>,
     pub recent_blockhash: Arc<RwLock<Hash>>,
@@ -41,6 +80,16 @@ pub struct OracleWorker {
 
     pub active_orders: Arc<DashSet<Pubkey>>,
     pub markets: Arc<DashMap<MarketType, Pubkey>>,
+    pub order_queues: Arc<DashMap<MarketType, Pubkey>>,
+    pub price_histories: Arc<DashMap<MarketType, Pubkey>>,
+    pub paused_markets: Arc<DashSet<MarketType>>,
+    /// Markets listed on chain that the worker has no price provider for
+    pub unsupported_markets: Arc<DashSet<[u8; 8]>>,
+    /// Orders the program rejected for exceeding their market's price deviation bound, which can
+    /// be resubmitted with the override flag if the operator allows it
+    pub deviation_rejected_orders: Arc<DashSet<Pubkey>>,
+    /// Each market's decimals, the submitted prices are scaled to them
+    pub market_decimals: Arc<DashMap<MarketType, u32>>,
 
     pub coinbase: CoinbaseProvider,
     pub pyth: PythProvider,
@@ -95,31 +144,15 @@ impl OracleWorker {
             &program_id
         );
 
-        let markets: DashMap<MarketType, Pubkey> = vec![
-            (
-                MarketType::Btc,
-                Pubkey::find_program_address(
-                    &[program_state_pubkey.to_bytes().as_ref(), b"BTC\0\0\0\0\0"],
-                    &program_id
-                ).0,
-            ),
-            (
-                MarketType::Eth,
-                Pubkey::find_program_address(
-                    &[program_state_pubkey.to_bytes().as_ref(), b"ETH\0\0\0\0\0"],
-                    &program_id
-                ).0,
-            ),
-            (
-                MarketType::Sol,
-                Pubkey::find_program_address(
-                    &[program_state_pubkey.to_bytes().as_ref(), b"SOL\0\0\0\0\0"],
-                    &program_id
-                ).0,
-            )
-        ]
-            .into_iter()
-            .collect();
+        let (stake_pubkey, _) = Pubkey::find_program_address(
+            &[b"STAKE", oracle_pubkey.to_bytes().as_ref()],
+            &program_id
+        );
+
+        let (event_authority_pubkey, _) = Pubkey::find_program_address(
+            &[EVENT_AUTHORITY_SEED],
+            &program_id
+        );
 
         Ok(Self {
             status: WorkerStatus::Initializing,
@@ -131,7 +164,13 @@ impl OracleWorker {
             pubsub_client: Arc::new(pubsub_client),
             program_state_pubkey,
             oracle_pubkey,
+            stake_pubkey,
+            event_authority_pubkey,
             enclave_signer,
+            attestation_validity_slots: 0,
+            order_close_grace_period: 0,
+            dispute_window: 0,
+            oracle_valid_until_slot: Default::default(),
             payer,
             payer_pubkey,
 
@@ -140,7 +179,13 @@ impl OracleWorker {
             slot: Default::default(),
 
             active_orders: Arc::new(DashSet::new()),
-            markets: Arc::new(markets),
+            markets: Arc::new(DashMap::new()),
+            order_queues: Arc::new(DashMap::new()),
+            price_histories: Arc::new(DashMap::new()),
+            paused_markets: Arc::new(DashSet::new()),
+            unsupported_markets: Arc::new(DashSet::new()),
+            deviation_rejected_orders: Arc::new(DashSet::new()),
+            market_decimals: Arc::new(DashMap::new()),
 
             coinbase: Default::default(),
             pyth: Default::default(),
@@ -175,16 +220,28 @@ impl OracleWorker {
                  Err(SbError::Message("watch_payer_balance returned unexpectedly"))
                 // panic!("watch_payer_balance returned unexpectedly");
             }
+            _ = self.watch_oracle_heartbeat(None) => {
+                 Err(SbError::Message("watch_oracle_heartbeat returned unexpectedly"))
+            }
+            _ = self.watch_market_accounts(None) => {
+                 Err(SbError::Message("watch_market_accounts returned unexpectedly"))
+            }
 
             // Watch on-chain events to respond to stale oracle prices
             _ = self.watch_anchor_events() => {
                  Err(SbError::Message("watch_anchor_events returned unexpectedly"))
                 // panic!("watch_anchor_events returned unexpectedly");
             }
+            _ = self.watch_order_queues(None) => {
+                 Err(SbError::Message("watch_order_queues returned unexpectedly"))
+            }
             _ = self.watch_open_order_accounts(None) => {
                  Err(SbError::Message("watch_open_order_accounts returned unexpectedly"))
                 // panic!("watch_open_order_accounts returned unexpectedly");
             }
+            _ = self.watch_fulfilled_order_accounts(None) => {
+                 Err(SbError::Message("watch_fulfilled_order_accounts returned unexpectedly"))
+            }
 
             // Watch data sources so our cache is fresh
             _ = self.coinbase.watch() => {
@@ -197,9 +254,19 @@ impl OracleWorker {
             }
         }
     }
-    async fn initialize_program_accounts(&self) -> Result<(), SbError> {
+    async fn initialize_program_accounts(&mut self) -> Result<(), SbError> {
         match self.rpc.get_account(&self.program_state_pubkey).await {
-            Ok(_account) => Ok(()),
+            Ok(account) => {
+                let program_state = ProgramAccount::try_deserialize(
+                    &mut account.data.as_slice()
+                ).map_err(|_| SbError::Message("Failed to deserialize program account"))?;
+                self.attestation_validity_slots = program_state.attestation_validity_slots;
+                self.order_close_grace_period = program_state.order_close_grace_period;
+                self.dispute_window = program_state.dispute_window;
+                self.load_markets(&program_state.markets);
+
+                Ok(())
+            }
             Err(e) => {
                 println!("Program accounts not initialized: {:?}", e);
                 Err(SbError::Message("Program accounts not initialized"))
@@ -212,9 +279,17 @@ impl OracleWorker {
         let enclave_signer_pubkey = enclave_signer.pubkey();
 
         if let Ok(account) = self.rpc.get_account(&self.oracle_pubkey).await {
-            if let Ok(oracle_account) = OracleAccount::deserialize(&mut account.data.as_slice()) {
+            if let Ok(oracle_account) = OracleAccount::try_deserialize(&mut account.data.as_slice()) {
                 if oracle_account.enclave_signer == enclave_signer_pubkey {
                     println!("Enclave signer already set, skipping");
+                    *self.oracle_valid_until_slot.write().await = oracle_account.valid_until_slot;
+
+                    // The signer may have been registered by a previous run whose attestation lapsed
+                    let slot = self.rpc.get_slot().await.unwrap_or_default();
+                    if self.is_heartbeat_due(slot).await {
+                        self.heartbeat().await?;
+                    }
+
                     return Ok(());
                 }
             } else {
@@ -229,7 +304,7 @@ impl OracleWorker {
                 Instruction {
                     program_id: self.program_id,
                     accounts: vec![
-                        AccountMeta::new(self.program_state_pubkey, false),
+                        AccountMeta::new_readonly(self.program_state_pubkey, false),
                         AccountMeta::new(self.oracle_pubkey, false),
                         AccountMeta::new_readonly(enclave_signer_pubkey, true),
                         AccountMeta::new_readonly(self.payer_pubkey, true),
@@ -258,9 +333,89 @@ impl OracleWorker {
             })?;
         info!("[ORACLE] initialized: {}", signature);
 
+        self.fetch_oracle_valid_until_slot().await;
+
+        Ok(())
+    }
+
+    /// Periodically re-attest the enclave signer before the oracle's validity window lapses.
+    async fn watch_oracle_heartbeat(&self, routine_interval: Option<u64>) {
+        start_routine(std::cmp::max(5, routine_interval.unwrap_or(30)), || {
+            Box::pin(async {
+                // self.slot holds the last valid block height, which trails the slot
+                let slot = self.rpc.get_slot().await.unwrap_or_default();
+                if slot > 0 && self.is_heartbeat_due(slot).await {
+                    if let Err(e) = self.heartbeat().await {
+                        error!("[ORACLE] heartbeat failed: {:?}", e);
+                    }
+                }
+
+                Ok(())
+            })
+        }).await.unwrap();
+    }
+
+    /// Re-attest once less than a quarter of the validity window remains
+    async fn is_heartbeat_due(&self, slot: u64) -> bool {
+        let valid_until_slot = *self.oracle_valid_until_slot.read().await;
+
+        valid_until_slot.saturating_sub(slot) <= self.attestation_validity_slots / 4
+    }
+
+    async fn heartbeat(&self) -> Result<(), SbError> {
+        let enclave_signer = self.enclave_signer.clone();
+        let signers = vec![self.payer.as_ref(), enclave_signer.deref()];
+
+        let msg = Message::new(
+            &[
+                Instruction {
+                    program_id: self.program_id,
+                    accounts: vec![
+                        AccountMeta::new_readonly(self.program_state_pubkey, false),
+                        AccountMeta::new(self.oracle_pubkey, false),
+                        AccountMeta::new_readonly(enclave_signer.pubkey(), true),
+                        AccountMeta::new_readonly(self.payer_pubkey, true)
+                    ],
+                    data: get_ixn_discriminator("heartbeat").to_vec(),
+                },
+            ],
+            Some(&self.payer_pubkey)
+        );
+        let mut tx = Transaction::new_unsigned(msg);
+
+        let blockhash = self.rpc.get_latest_blockhash().await.unwrap_or_default();
+
+        tx.try_sign(&signers, blockhash).map_err(|e| SbError::CustomError {
+            message: "Failed to sign txn".into(),
+            source: std::sync::Arc::new(e),
+        })?;
+
+        let signature = self.rpc
+            .send_and_confirm_transaction(&tx).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to send txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+        info!("[ORACLE] heartbeat: {}", signature);
+
+        self.fetch_oracle_valid_until_slot().await;
+
         Ok(())
     }
 
+    async fn fetch_oracle_valid_until_slot(&self) {
+        match self.rpc.get_account(&self.oracle_pubkey).await {
+            Ok(account) => {
+                if let Ok(oracle_account) = OracleAccount::try_deserialize(&mut account.data.as_slice()) {
+                    *self.oracle_valid_until_slot.write().await = oracle_account.valid_until_slot;
+                } else {
+                    error!("Failed to deserialize oracle account");
+                }
+            }
+            Err(e) => error!("Failed to fetch oracle account: {:?}", e),
+        }
+    }
+
     /// Periodically fetch the Solana time from on-chain so we know when to execute functions.
     async fn watch_blockhash_and_slot(&self, routine_interval: Option<u64>) {
         start_routine(std::cmp::max(1, routine_interval.unwrap_or(1)), || {
@@ -302,7 +457,17 @@ impl OracleWorker {
 
     async fn fetch_payer_balance(&self) {
         match self.rpc.get_balance(&self.payer_pubkey).await {
-            Ok(balance) => {
+            Ok(mut balance) => {
+                // Top up the payer with the fees the oracle earned from fulfilling orders
+                if balance < WITHDRAW_FEES_BALANCE_THRESHOLD {
+                    match self.withdraw_fees().await {
+                        Ok(amount) => {
+                            balance += amount;
+                        }
+                        Err(e) => error!("Failed to withdraw oracle fees: {:?}", e),
+                    }
+                }
+
                 let payer_balance_decimal = SwitchboardDecimal {
                     mantissa: balance.try_into().unwrap(),
                     scale: 9,
@@ -330,9 +495,244 @@ impl OracleWorker {
         }
     }
 
-    /// Call getProgramAccounts and find open orders that are ready to be executed.
-    async fn watch_open_order_accounts(&self, routine_interval: Option<u64>) {
+    /// Withdraw the oracle's unclaimed order fees to the payer, returning the lamports withdrawn
+    async fn withdraw_fees(&self) -> Result<u64, SbError> {
+        let oracle_account = self.rpc
+            .get_account(&self.oracle_pubkey).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to fetch oracle account".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+        let unclaimed_fees = OracleAccount::try_deserialize(&mut oracle_account.data.as_slice())
+            .map_err(|_| SbError::Message("Failed to deserialize oracle account"))?
+            .unclaimed_fees;
+        if unclaimed_fees == 0 {
+            return Ok(0);
+        }
+
+        let msg = Message::new(
+            &[
+                Instruction {
+                    program_id: self.program_id,
+                    accounts: vec![
+                        AccountMeta::new(self.oracle_pubkey, false),
+                        AccountMeta::new_readonly(self.payer_pubkey, true),
+                        AccountMeta::new(self.payer_pubkey, false)
+                    ],
+                    data: get_ixn_discriminator("withdraw_fees").to_vec(),
+                },
+            ],
+            Some(&self.payer_pubkey)
+        );
+        let mut tx = Transaction::new_unsigned(msg);
+
+        tx
+            .try_sign(&[self.payer.as_ref()], *self.recent_blockhash.read().await)
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to sign txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        let signature = self.rpc
+            .send_and_confirm_transaction(&tx).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to send txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+        info!("[ORACLE] withdraw_fees ({} lamports): {}", unclaimed_fees, signature);
+
+        Ok(unclaimed_fees)
+    }
+
+    /// Track the markets listed in the program account. Markets the worker has no price provider
+    /// for are logged once and their orders are skipped.
+    fn load_markets(&self, names: &[[u8; 8]]) {
+        for name in names {
+            let market = match MarketType::try_from(*name) {
+                Ok(market) => market,
+                Err(_) => {
+                    if self.unsupported_markets.insert(*name) {
+                        error!("[MARKET] {} has no price provider, skipping", market_name(name));
+                    }
+                    continue;
+                }
+            };
+            if self.markets.contains_key(&market) {
+                continue;
+            }
+
+            let (market_pubkey, _) = Pubkey::find_program_address(
+                &[self.program_state_pubkey.to_bytes().as_ref(), name.as_ref()],
+                &self.program_id
+            );
+            let (order_queue_pubkey, _) = Pubkey::find_program_address(
+                &[b"QUEUE", market_pubkey.to_bytes().as_ref()],
+                &self.program_id
+            );
+            let (price_history_pubkey, _) = Pubkey::find_program_address(
+                &[b"HISTORY", market_pubkey.to_bytes().as_ref()],
+                &self.program_id
+            );
+
+            info!("[MARKET] {:?} loaded: {}", market, market_pubkey);
+            self.order_queues.insert(market.clone(), order_queue_pubkey);
+            self.price_histories.insert(market.clone(), price_history_pubkey);
+            self.markets.insert(market, market_pubkey);
+        }
+    }
+
+    /// Periodically fetch the market accounts so we pick up new markets and stop fulfilling
+    /// orders for paused markets.
+    async fn watch_market_accounts(&self, routine_interval: Option<u64>) {
+        start_routine(std::cmp::max(1, routine_interval.unwrap_or(10)), || {
+            Box::pin(async {
+                self.fetch_market_accounts().await;
+
+                Ok(())
+            })
+        }).await.unwrap();
+    }
+
+    async fn fetch_market_accounts(&self) {
+        match self.rpc.get_account(&self.program_state_pubkey).await {
+            Ok(account) => {
+                match ProgramAccount::try_deserialize(&mut account.data.as_slice()) {
+                    Ok(program_state) => self.load_markets(&program_state.markets),
+                    Err(e) => error!("Failed to deserialize program account: {:?}", e),
+                }
+            }
+            Err(e) => error!("Failed to fetch program account: {:?}", e),
+        }
+
+        let (markets, market_pubkeys): (Vec<MarketType>, Vec<Pubkey>) = self.markets
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .unzip();
+
+        match self.rpc.get_multiple_accounts(&market_pubkeys).await {
+            Ok(accounts) => {
+                for (market, account) in markets.into_iter().zip(accounts) {
+                    let market_state = account.and_then(|account| {
+                        MarketAccount::try_deserialize(&mut account.data.as_slice()).ok()
+                    });
+
+                    if let Some(market_state) = &market_state {
+                        let decimals = self.market_decimals.insert(
+                            market.clone(),
+                            market_state.decimals
+                        );
+                        if decimals != Some(market_state.decimals) {
+                            info!(
+                                "[MARKET] {:?} prices use {} decimals",
+                                market,
+                                market_state.decimals
+                            );
+                        }
+                    }
+
+                    match market_state {
+                        Some(market_state) if !market_state.paused => {
+                            if self.paused_markets.remove(&market).is_some() {
+                                info!("[MARKET] {:?} unpaused", market);
+                            }
+                        }
+                        _ => {
+                            if self.paused_markets.insert(market.clone()) {
+                                info!("[MARKET] {:?} paused or not found", market);
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => error!("Failed to fetch market accounts: {:?}", e),
+        }
+    }
+
+    /// Read each market's order queue and fulfill any open orders we have not picked up yet.
+    async fn watch_order_queues(&self, routine_interval: Option<u64>) {
         start_routine(std::cmp::max(1, routine_interval.unwrap_or(1)), || {
+            Box::pin(async {
+                let queued_orders = self.fetch_queued_orders().await.unwrap_or_default();
+                if queued_orders.is_empty() {
+                    return Ok(());
+                }
+
+                let orders = self.select_open_orders(queued_orders);
+                self.fulfill_open_orders(orders).await;
+
+                Ok(())
+            })
+        }).await.unwrap();
+    }
+
+    /// Fetch the order accounts in each market's order queue that we are not already processing
+    async fn fetch_queued_orders(&self) -> Result<Vec<(Pubkey, OrderAccount)>, SbError> {
+        let (markets, order_queue_pubkeys): (Vec<MarketType>, Vec<Pubkey>) = self.order_queues
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .unzip();
+
+        let accounts = self.rpc
+            .get_multiple_accounts(&order_queue_pubkeys).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to fetch order queues".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        let now = chrono::Utc::now().timestamp();
+
+        let mut order_pubkeys = Vec::new();
+        for (market, account) in markets.into_iter().zip(accounts) {
+            if self.paused_markets.contains(&market) {
+                continue;
+            }
+
+            let order_queue = match
+                account.and_then(|account| {
+                    OrderQueueAccount::try_deserialize(&mut account.data.as_slice()).ok()
+                })
+            {
+                Some(order_queue) => order_queue,
+                None => {
+                    debug!("[QUEUE] Order queue for {:?} not found", market);
+                    continue;
+                }
+            };
+
+            order_pubkeys.extend(
+                order_queue
+                    .open_orders(now)
+                    .into_iter()
+                    .map(|queued_order| queued_order.order)
+                    .filter(|order| !self.active_orders.contains(order))
+            );
+        }
+
+        // The queue only tracks the order addresses so load the orders to see their submissions
+        // and callbacks
+        let mut orders = Vec::new();
+        for chunk in order_pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = self.rpc
+                .get_multiple_accounts(chunk).await
+                .map_err(|e| SbError::CustomError {
+                    message: "Failed to fetch queued orders".into(),
+                    source: std::sync::Arc::new(e),
+                })?;
+
+            for (order_pubkey, account) in chunk.iter().zip(accounts) {
+                if let Some(order) = account.and_then(|account| parse_order(&account.data)) {
+                    orders.push((*order_pubkey, order));
+                }
+            }
+        }
+
+        Ok(orders)
+    }
+
+    /// Call getProgramAccounts and find open orders that are ready to be executed. The order queues
+    /// are the primary source of work so this runs less often to catch anything they missed.
+    async fn watch_open_order_accounts(&self, routine_interval: Option<u64>) {
+        start_routine(std::cmp::max(1, routine_interval.unwrap_or(30)), || {
             Box::pin(async {
                 let open_orders = self.fetch_open_order_accounts().await.unwrap_or_default();
                 if open_orders.is_empty() {
@@ -341,45 +741,430 @@ impl OracleWorker {
 
                 info!("Found {} open orders to fulfill", open_orders.len());
 
-                // Vector to store futures
-                let mut futures = Vec::new();
-                let mut order_keys = Vec::new();
+                let orders = self.select_open_orders(open_orders);
+                self.fulfill_open_orders(orders).await;
 
-                for (order_key, order_data) in open_orders {
-                    if self.active_orders.insert(order_key) {
-                        info!("[ORDER] Found open order {}", order_key);
+                Ok(())
+            })
+        }).await.unwrap();
+    }
 
-                        let future = self.fulfill_order(
-                            order_key,
-                            MarketType::try_from(order_data.market_name).unwrap(),
-                            order_data.open_timestamp
-                        );
-                        futures.push(future);
-                        order_keys.push(order_key);
+    /// Filter open orders down to the ones this oracle still needs to price and mark them active
+    fn select_open_orders(
+        &self,
+        open_orders: Vec<(Pubkey, OrderAccount)>
+    ) -> Vec<(Pubkey, MarketType, OrderAccount)> {
+        let mut orders = Vec::new();
+
+        let now = chrono::Utc::now().timestamp();
+        for (order_key, order_data) in open_orders {
+            // Expired orders can no longer be fulfilled, the authority has to cancel them
+            if order_data.is_expired(now) {
+                debug!("[ORDER] Skipping expired order {}", order_key);
+                continue;
+            }
+
+            // Orders stay open until the quorum is reached so skip ones we already priced
+            if order_data.submissions().iter().any(|s| s.oracle == self.oracle_pubkey) {
+                continue;
+            }
+
+            let market = match MarketType::try_from(order_data.market_name) {
+                Ok(market) => market,
+                Err(_) => {
+                    debug!("[ORDER] Skipping order {} for unsupported market", order_key);
+                    continue;
+                }
+            };
+
+            if self.paused_markets.contains(&market) {
+                debug!("[ORDER] Skipping order {} for paused market", order_key);
+                continue;
+            }
+
+            // TWAP orders can't be fulfilled until their window has closed
+            if now < order_data.price_timestamp() {
+                continue;
+            }
+
+            if self.active_orders.insert(order_key) {
+                info!("[ORDER] Found open order {}", order_key);
+                orders.push((order_key, market, order_data));
+            }
+        }
+
+        orders
+    }
+
+    /// Price the orders and submit them in as few fulfill_orders transactions as possible. Orders
+    /// with a callback need their callback accounts so they are fulfilled one at a time.
+    async fn fulfill_open_orders(&self, orders: Vec<(Pubkey, MarketType, OrderAccount)>) {
+        let (callback_orders, orders): (Vec<_>, Vec<_>) = orders
+            .into_iter()
+            .partition(|(_, _, order_data)| order_data.has_callback == 1);
+
+        let callback_results = join_all(
+            callback_orders
+                .iter()
+                .map(|(order_key, market, order_data)| {
+                    self.fulfill_order(
+                        *order_key,
+                        market.clone(),
+                        order_data.authority,
+                        order_data.requested_timestamp,
+                        order_data.twap_window,
+                        order_data.candle_end,
+                        order_data.callback()
+                    )
+                })
+        ).await;
+
+        for ((order_key, _, _), result) in callback_orders.iter().zip(callback_results) {
+            if let Err(e) = result {
+                error!("[ORDER] order {} failed: {:?}", order_key, e);
+                self.active_orders.remove(order_key);
+            }
+        }
+
+        // Resolve all of the prices before packing them into transactions
+        let prices = join_all(
+            orders
+                .iter()
+                .map(|(_, market, order_data)| {
+                    self.get_order_price(
+                        market,
+                        order_data.requested_timestamp,
+                        order_data.twap_window,
+                        order_data.candle_end
+                    )
+                })
+        ).await;
+
+        let mut priced_orders = Vec::new();
+        for ((order_key, market, order_data), result) in orders.into_iter().zip(prices) {
+            match result {
+                Ok(price) => priced_orders.push((order_key, market, order_data.authority, price)),
+                Err(e) => {
+                    error!("[ORDER] failed to get price for {}: {:?}", order_key, e);
+                    self.active_orders.remove(&order_key);
+                }
+            }
+        }
+
+        let batches = self.pack_fulfill_batches(priced_orders);
+
+        let results = join_all(
+            batches.iter().map(|batch| self.fulfill_orders(batch))
+        ).await;
+
+        for (batch, result) in batches.iter().zip(results) {
+            match result {
+                Ok(signature) => {
+                    let order_keys: Vec<Pubkey> = batch
+                        .iter()
+                        .map(|(order_key, _, _, _)| *order_key)
+                        .collect();
+                    let skipped = self.release_skipped_orders(&order_keys, &signature).await;
+                    info!("[ORDER] {} orders fulfilled", batch.len() - skipped);
+                }
+                Err(e) => {
+                    error!("[ORDER] batch of {} orders failed: {:?}", batch.len(), e);
+                    for (order_key, _, _, _) in batch {
+                        self.active_orders.remove(order_key);
                     }
                 }
+            }
+        }
+    }
 
-                // Wait for all futures to complete
-                let results = join_all(futures).await;
-
-                // Process the results
-                for (i, result) in results.iter().enumerate() {
-                    match result {
-                        Ok(_) => {
-                            if let Some(order_key) = order_keys.get(i) {
-                                info!("[ORDER] order fulfilled {:?}", order_key);
-                            } else {
-                                info!("[ORDER] order fulfilled");
-                            }
-                        }
-                        Err(e) => {
-                            error!("[ORDER] order failed: {:?}", e);
-                            if let Some(order_key) = order_keys.get(i) {
-                                self.active_orders.remove(order_key);
-                            } else {
-                                error!("Failed to find order key");
-                            }
-                        }
+    /// fulfill_orders skips the orders whose price the program rejects rather than failing the
+    /// batch, so re-read the orders once the batch confirms and release any that are still open
+    /// without our submission to be picked up again. Returns the number of skipped orders.
+    async fn release_skipped_orders(&self, order_keys: &[Pubkey], signature: &Signature) -> usize {
+        let accounts = match self.rpc.get_multiple_accounts(order_keys).await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                // Selecting an order checks for our submission so it's safe to release them all
+                error!("[ORDER] failed to re-read fulfilled orders: {:?}", e);
+                for order_key in order_keys {
+                    self.active_orders.remove(order_key);
+                }
+                return 0;
+            }
+        };
+
+        let mut skipped = Vec::new();
+        for (order_key, account) in order_keys.iter().zip(accounts) {
+            let order = match account.and_then(|account| parse_order(&account.data)) {
+                Some(order) => order,
+                None => continue,
+            };
+
+            if
+                order.open_order == 1 &&
+                !order.submissions().iter().any(|s| s.oracle == self.oracle_pubkey)
+            {
+                info!("[ORDER] order {} was skipped by fulfill_orders", order_key);
+                skipped.push(*order_key);
+            } else {
+                self.deviation_rejected_orders.remove(order_key);
+            }
+        }
+
+        if !skipped.is_empty() {
+            match self.fetch_deviation_rejected_orders(signature).await {
+                Ok(rejected) => {
+                    for order_key in rejected {
+                        info!("[ORDER] order {} exceeded its price deviation bound", order_key);
+                        self.deviation_rejected_orders.insert(order_key);
+                    }
+                }
+                Err(e) => error!("[ORDER] failed to fetch skipped orders: {:?}", e),
+            }
+        }
+
+        // Release the orders once they're marked so they're resubmitted with the override
+        for order_key in skipped.iter() {
+            self.active_orders.remove(order_key);
+        }
+
+        skipped.len()
+    }
+
+    /// The orders a fulfill_orders transaction skipped because their price exceeded the market's
+    /// deviation bound, parsed from the program's logs. The transaction was only confirmed at the
+    /// processed commitment so it's retried until it can be fetched.
+    async fn fetch_deviation_rejected_orders(
+        &self,
+        signature: &Signature
+    ) -> Result<Vec<Pubkey>, SbError> {
+        let mut attempts = 0;
+        let txn = loop {
+            match
+                self.rpc.get_transaction_with_config(signature, RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                }).await
+            {
+                Ok(txn) => {
+                    break txn;
+                }
+                Err(e) if attempts >= MAX_FETCH_TXN_ATTEMPTS => {
+                    return Err(SbError::CustomError {
+                        message: "Failed to fetch txn".into(),
+                        source: std::sync::Arc::new(e),
+                    });
+                }
+                Err(_) => {
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            }
+        };
+
+        let logs = match txn.transaction.meta.map(|meta| meta.log_messages) {
+            Some(OptionSerializer::Some(logs)) => logs,
+            _ => {
+                return Ok(vec![]);
+            }
+        };
+
+        // fulfill_orders logs "Skipping order <order>: <error>" for each order it skips
+        let error_name = BackfillOracleError::PriceDeviationExceeded.name();
+        Ok(
+            logs
+                .iter()
+                .filter(|log| log.contains(&error_name))
+                .filter_map(|log| log.split("Skipping order ").nth(1))
+                .filter_map(|log| log.split(':').next())
+                .filter_map(|order_key| Pubkey::from_str(order_key).ok())
+                .collect()
+        )
+    }
+
+    /// Whether to flag the order's price as a deviation override. Only orders the program has
+    /// already rejected for deviation and the operator listed, directly or by market, qualify.
+    fn override_deviation(&self, order_key: &Pubkey, market: &MarketType) -> bool {
+        if !self.deviation_rejected_orders.contains(order_key) {
+            return false;
+        }
+
+        let env = WorkerEnvironment::get_or_init();
+        env.override_price_deviation_orders.iter().any(|order| *order == order_key.to_string()) ||
+            env.override_price_deviation_markets
+                .iter()
+                .any(|name| MarketType::from_str(name).ok().as_ref() == Some(market))
+    }
+
+    /// The order authority's RateLimitAccount in a market, which the program releases the order
+    /// from once it settles
+    fn rate_limit_pubkey(&self, market: &Pubkey, authority: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"RATE_LIMIT", market.as_ref(), authority.as_ref()],
+            &self.program_id
+        ).0
+    }
+
+    /// Greedily pack priced orders into batches that fit within the transaction size and compute limits
+    fn pack_fulfill_batches(
+        &self,
+        orders: Vec<(Pubkey, MarketType, Pubkey, ProviderPrice)>
+    ) -> Vec<Vec<(Pubkey, MarketType, Pubkey, ProviderPrice)>> {
+        let mut batches: Vec<Vec<(Pubkey, MarketType, Pubkey, ProviderPrice)>> = Vec::new();
+        let mut batch: Vec<(Pubkey, MarketType, Pubkey, ProviderPrice)> = Vec::new();
+
+        for order in orders {
+            batch.push(order);
+
+            let exceeds_compute = batch.len() > MAX_ORDERS_PER_COMPUTE_BUDGET;
+            let exceeds_size = self.fulfill_orders_txn_size(&batch) > PACKET_DATA_SIZE;
+
+            if (exceeds_compute || exceeds_size) && batch.len() > 1 {
+                let order = batch.pop().unwrap();
+                batches.push(std::mem::take(&mut batch));
+                batch.push(order);
+            }
+        }
+
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+
+        batches
+    }
+
+    /// The serialized size of a signed fulfill_orders transaction for the batch
+    fn fulfill_orders_txn_size(&self, batch: &[(Pubkey, MarketType, Pubkey, ProviderPrice)]) -> usize {
+        let msg = Message::new(&self.build_fulfill_orders_ixns(batch), Some(&self.payer_pubkey));
+
+        // compact-u16 signature count + payer and enclave signer signatures
+        1 + 2 * 64 + msg.serialize().len()
+    }
+
+    fn build_fulfill_orders_ixns(&self, batch: &[(Pubkey, MarketType, Pubkey, ProviderPrice)]) -> Vec<Instruction> {
+        let mut ixn_data = get_ixn_discriminator("fulfill_orders").to_vec();
+        let ixn_params = FulfillOrdersParams {
+            orders: batch
+                .iter()
+                .map(|(order_key, market, _, price)| FulfillOrderParams {
+                    market: market.to_bytes(),
+                    price: price.price,
+                    publish_time: price.publish_time,
+                    confidence: price.confidence,
+                    num_sources: price.num_sources(),
+                    source_bitmap: price.source_bitmap,
+                    num_samples: price.num_samples,
+                    candle: price.candle,
+                    override_deviation: self.override_deviation(order_key, market),
+                })
+                .collect(),
+        };
+        ixn_data.append(&mut ixn_params.try_to_vec().unwrap());
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(self.program_state_pubkey, false),
+            AccountMeta::new(self.oracle_pubkey, false),
+            AccountMeta::new(self.stake_pubkey, false),
+            AccountMeta::new_readonly(self.enclave_signer.pubkey(), true),
+            AccountMeta::new_readonly(self.event_authority_pubkey, false),
+            AccountMeta::new_readonly(self.program_id, false)
+        ];
+        for (order_pubkey, market, authority, _) in batch {
+            let market_pubkey = *self.markets.get(market).unwrap();
+            accounts.push(AccountMeta::new(*order_pubkey, false));
+            accounts.push(AccountMeta::new(market_pubkey, false));
+            accounts.push(AccountMeta::new(*self.order_queues.get(market).unwrap(), false));
+            accounts.push(AccountMeta::new(*self.price_histories.get(market).unwrap(), false));
+            accounts.push(AccountMeta::new(self.rate_limit_pubkey(&market_pubkey, authority), false));
+        }
+
+        let compute_units = std::cmp::min(
+            FULFILL_ORDERS_BASE_COMPUTE_UNITS +
+                (batch.len() as u32) * FULFILL_ORDERS_COMPUTE_UNITS_PER_ORDER,
+            MAX_COMPUTE_UNITS
+        );
+
+        vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(compute_units),
+            Instruction {
+                program_id: self.program_id,
+                accounts,
+                data: ixn_data,
+            }
+        ]
+    }
+
+    async fn fulfill_orders(
+        &self,
+        batch: &[(Pubkey, MarketType, Pubkey, ProviderPrice)]
+    ) -> Result<Signature, SbError> {
+        let enclave_signer = self.enclave_signer.clone();
+        let signers = vec![self.payer.as_ref(), enclave_signer.deref()];
+
+        let msg = Message::new(&self.build_fulfill_orders_ixns(batch), Some(&self.payer_pubkey));
+        let mut tx = Transaction::new_unsigned(msg);
+
+        tx
+            .try_sign(&signers, *self.recent_blockhash.read().await)
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to sign txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        let signature = self.rpc
+            .send_and_confirm_transaction(&tx).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to send txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        info!("[ORACLE] fulfill_orders ({}): {}", batch.len(), signature);
+
+        Ok(signature)
+    }
+
+    /// Periodically close fulfilled orders whose grace period has elapsed and reclaim the rent for
+    /// their payers. This is opt-in with the SWEEP_FULFILLED_ORDERS env variable.
+    async fn watch_fulfilled_order_accounts(&self, routine_interval: Option<u64>) {
+        if !WorkerEnvironment::get_or_init().sweep_fulfilled_orders {
+            return futures::future::pending().await;
+        }
+
+        start_routine(std::cmp::max(30, routine_interval.unwrap_or(60)), || {
+            Box::pin(async {
+                let fulfilled_orders = self.fetch_order_accounts(0, None).await.unwrap_or_default();
+
+                let now = chrono::Utc::now().timestamp();
+                let closable_orders: Vec<(Pubkey, OrderAccount)> = fulfilled_orders
+                    .into_iter()
+                    .filter(|(_, order)| {
+                        // Orders the oracles priced can't be closed until the dispute window has
+                        // elapsed too, orders settled from Pyth can't be disputed
+                        let closable_after = if order.num_submissions == 0 {
+                            self.order_close_grace_period
+                        } else {
+                            std::cmp::max(self.order_close_grace_period, self.dispute_window)
+                        };
+                        order.dispute.status() != DisputeStatus::Disputed &&
+                            order.close_timestamp.saturating_add(closable_after) <= now
+                    })
+                    .collect();
+                if closable_orders.is_empty() {
+                    return Ok(());
+                }
+
+                info!("Found {} fulfilled orders to close", closable_orders.len());
+
+                let results = join_all(
+                    closable_orders
+                        .chunks(MAX_CLOSE_ORDERS_PER_TXN)
+                        .map(|batch| self.close_orders(batch))
+                ).await;
+
+                for result in results {
+                    if let Err(e) = result {
+                        error!("[ORDER] failed to close orders: {:?}", e);
                     }
                 }
 
@@ -387,25 +1172,85 @@ impl OracleWorker {
             })
         }).await.unwrap();
     }
-    /// Fetch all of the open orders based on the 8-byte discriminator and the open_order flag
+
+    async fn close_orders(&self, batch: &[(Pubkey, OrderAccount)]) -> Result<(), SbError> {
+        let ixns: Vec<Instruction> = batch
+            .iter()
+            .map(|(order_pubkey, order)| Instruction {
+                program_id: self.program_id,
+                accounts: vec![
+                    AccountMeta::new(*order_pubkey, false),
+                    AccountMeta::new_readonly(self.program_state_pubkey, false),
+                    AccountMeta::new(order.payer, false),
+                    AccountMeta::new_readonly(self.payer_pubkey, true)
+                ],
+                data: get_ixn_discriminator("close_order").to_vec(),
+            })
+            .collect();
+
+        let msg = Message::new(&ixns, Some(&self.payer_pubkey));
+        let mut tx = Transaction::new_unsigned(msg);
+
+        tx
+            .try_sign(&[self.payer.as_ref()], *self.recent_blockhash.read().await)
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to sign txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        let signature = self.rpc
+            .send_and_confirm_transaction(&tx).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to send txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        info!("[ORACLE] close_order ({}): {}", batch.len(), signature);
+
+        Ok(())
+    }
+
+    /// Fetch all of the open orders with one getProgramAccounts call per market so each response
+    /// only covers a shard of the orders
     async fn fetch_open_order_accounts(&self) -> Result<Vec<(Pubkey, OrderAccount)>, SbError> {
-        let mut open_orders: Vec<(Pubkey, OrderAccount)> = vec![];
+        let markets: Vec<Pubkey> = self.markets
+            .iter()
+            .map(|market| *market.value())
+            .collect();
+
+        let results = join_all(
+            markets.iter().map(|market| self.fetch_order_accounts(1, Some(market)))
+        ).await;
+
+        let mut orders = vec![];
+        for result in results {
+            orders.extend(result?);
+        }
+
+        Ok(orders)
+    }
 
-        let mut order_account_discriminator_filter = OrderAccount::discriminator().to_vec();
-        order_account_discriminator_filter.push(1);
+    /// Fetch the order accounts with the given open_order flag, optionally only those in a market.
+    /// Orders still in the legacy layout are left out until they're migrated.
+    async fn fetch_order_accounts(
+        &self,
+        open_order: u8,
+        market: Option<&Pubkey>
+    ) -> Result<Vec<(Pubkey, OrderAccount)>, SbError> {
+        let mut orders: Vec<(Pubkey, OrderAccount)> = vec![];
+
+        let mut filters = vec![
+            Memcmp::new_raw_bytes(0, OrderAccount::discriminator().to_vec()),
+            Memcmp::new_raw_bytes(ORDER_OPEN_OFFSET, vec![open_order]),
+            Memcmp::new_raw_bytes(ORDER_VERSION_OFFSET, vec![ORDER_ACCOUNT_VERSION])
+        ];
+        if let Some(market) = market {
+            filters.push(Memcmp::new_raw_bytes(ORDER_MARKET_OFFSET, market.to_bytes().to_vec()));
+        }
 
         let accounts = self.rpc
             .get_program_accounts_with_config(&self.program_id, RpcProgramAccountsConfig {
-                filters: Some(
-                    vec![
-                        solana_client::rpc_filter::RpcFilterType::Memcmp(
-                            solana_client::rpc_filter::Memcmp::new_raw_bytes(
-                                0,
-                                order_account_discriminator_filter
-                            )
-                        )
-                    ]
-                ),
+                filters: Some(filters.into_iter().map(RpcFilterType::Memcmp).collect()),
                 account_config: RpcAccountInfoConfig {
                     encoding: Some(solana_account_decoder::UiAccountEncoding::Base64Zstd),
                     ..Default::default()
@@ -418,12 +1263,12 @@ impl OracleWorker {
             })?;
 
         for (pubkey, account) in accounts {
-            if let Ok(order_data) = OrderAccount::try_deserialize(&mut &account.data[..]) {
-                open_orders.push((pubkey, order_data));
+            if let Some(order_data) = parse_order(&account.data) {
+                orders.push((pubkey, order_data));
             }
         }
 
-        Ok(open_orders)
+        Ok(orders)
     }
 
     /// Stream websocket events for the request trigger event
@@ -437,7 +1282,8 @@ impl OracleWorker {
             let connection_result = self.pubsub_client.logs_subscribe(
                 RpcTransactionLogsFilter::Mentions(vec![self.program_id.to_string()]),
                 RpcTransactionLogsConfig {
-                    commitment: Some(CommitmentConfig::processed()),
+                    // Transactions can only be fetched once they're confirmed
+                    commitment: Some(CommitmentConfig::confirmed()),
                 }
             ).await;
 
@@ -448,37 +1294,47 @@ impl OracleWorker {
 
                     // Process events if connection is successful
 
-                    while let Some(event) = stream.next().await {
-                        let log: String = event.value.logs.join(" ");
-                        for w in log.split(' ') {
-                            let decoded = general_purpose::STANDARD.decode(w);
-                            if decoded.is_err() {
+                    // The logs only tell us which transactions to fetch, the events are decoded
+                    // from their inner instructions so log truncation can't drop them
+                    while let Some(notification) = stream.next().await {
+                        // Failed transactions are rolled back along with their events
+                        if notification.value.err.is_some() {
+                            continue;
+                        }
+
+                        let signature = match Signature::from_str(&notification.value.signature) {
+                            Ok(signature) => signature,
+                            Err(_) => {
                                 continue;
                             }
-                            let decoded = decoded.unwrap();
-                            if decoded.len() < 8 {
+                        };
+
+                        let events = match self.fetch_cpi_events(&signature).await {
+                            Ok(events) => events,
+                            Err(e) => {
+                                error!("[EVENTS] Failed to fetch events for {}: {:?}", signature, e);
                                 continue;
                             }
+                        };
 
-                            if decoded[..8] == OraclePriceRequestedEvent::DISCRIMINATOR {
+                        for data in events {
+                            if data[..8] == OraclePriceRequestedEvent::DISCRIMINATOR {
                                 if
                                     let Ok(event) = OraclePriceRequestedEvent::try_from_slice(
-                                        &decoded[8..]
+                                        &data[8..]
                                     )
                                 {
                                     self.handle_price_request_event(event).await;
                                 }
-                            } else if decoded[..8] == OraclePriceFulfilledEvent::DISCRIMINATOR {
+                            } else if data[..8] == OraclePriceFulfilledEvent::DISCRIMINATOR {
                                 if
                                     let Ok(event) = OraclePriceFulfilledEvent::try_from_slice(
-                                        &decoded[8..]
+                                        &data[8..]
                                     )
                                 {
                                     self.handle_order_fulfilled_event(event).await;
                                 }
                             }
-
-                            continue;
                         }
                     }
 
@@ -504,11 +1360,92 @@ impl OracleWorker {
         }
     }
 
+    /// Fetch a transaction and decode the events the program emitted through self-CPI. Each event
+    /// is returned as its discriminator followed by the serialized event.
+    async fn fetch_cpi_events(&self, signature: &Signature) -> Result<Vec<Vec<u8>>, SbError> {
+        let txn = self.rpc
+            .get_transaction_with_config(signature, RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            }).await
+            .map_err(|e| SbError::CustomError {
+                message: "Failed to fetch txn".into(),
+                source: std::sync::Arc::new(e),
+            })?;
+
+        // Program ids are always static keys so the lookup table addresses aren't needed
+        let account_keys = match txn.transaction.transaction.decode() {
+            Some(versioned_txn) => versioned_txn.message.static_account_keys().to_vec(),
+            None => {
+                return Err(SbError::Message("Failed to decode txn"));
+            }
+        };
+
+        let inner_instructions = match txn.transaction.meta.map(|meta| meta.inner_instructions) {
+            Some(OptionSerializer::Some(inner_instructions)) => inner_instructions,
+            _ => {
+                return Ok(vec![]);
+            }
+        };
+
+        Ok(
+            inner_instructions
+                .into_iter()
+                .flat_map(|inner| inner.instructions)
+                .filter_map(|ixn| {
+                    match ixn {
+                        UiInstruction::Compiled(ixn) => Some(ixn),
+                        _ => None,
+                    }
+                })
+                .filter(|ixn| account_keys.get(ixn.program_id_index as usize) == Some(&self.program_id))
+                .filter_map(|ixn| solana_sdk::bs58::decode(ixn.data).into_vec().ok())
+                .filter(|data| data.len() >= 16 && data[..8] == anchor_lang::event::EVENT_IX_TAG_LE)
+                .map(|data| data[8..].to_vec())
+                .collect()
+        )
+    }
+
     async fn handle_price_request_event(&self, event: OraclePriceRequestedEvent) {
         println!("[OraclePriceRequestedEvent] {:#?}", event);
 
+        if chrono::Utc::now().timestamp() > event.expiry_timestamp {
+            debug!("[ORDER] Skipping expired order {}", event.order);
+            return;
+        }
+
+        let market = match MarketType::try_from(event.market) {
+            Ok(market) => market,
+            Err(_) => {
+                error!("[ORDER] Unsupported market for order {}", event.order);
+                return;
+            }
+        };
+
+        if self.paused_markets.contains(&market) {
+            debug!("[ORDER] Skipping order {} for paused market", event.order);
+            return;
+        }
+
+        // TWAP and candle orders are picked up from the order queue once their window has closed
+        if event.twap_window > 0 || event.candle_end > 0 {
+            debug!("[ORDER] Deferring order {} until its window closes", event.order);
+            return;
+        }
+
         if self.active_orders.insert(event.order) {
-            match self.fulfill_order(event.order, event.market, event.timestamp).await {
+            match
+                self.fulfill_order(
+                    event.order,
+                    market,
+                    event.authority,
+                    event.timestamp,
+                    event.twap_window,
+                    event.candle_end,
+                    event.callback.clone()
+                ).await
+            {
                 Ok(_) => {
                     info!("[ORDER] order fulfilled");
                     // TODO: should we remove from the map after some delay so it doesnt get processed twice?
@@ -526,7 +1463,7 @@ impl OracleWorker {
         println!("[OraclePriceFulfilledEvent] {:#?}", event);
     }
 
-    async fn get_price(&self, market: &MarketType, timestamp: i64) -> Result<u64, SbError> {
+    async fn get_price(&self, market: &MarketType, timestamp: i64) -> Result<ProviderPrice, SbError> {
         let (pyth, coinbase) = match market {
             MarketType::Btc => (&self.pyth.btc, &self.coinbase.btc),
             MarketType::Eth => (&self.pyth.eth, &self.coinbase.eth),
@@ -539,7 +1476,18 @@ impl OracleWorker {
         // If the coinbase websocket has provided a price, use the average price of pyth + coinbase
         // This is for demo purposes only - three or more sources should be combined with a median
         let price = if let Some(coinbase_price) = coinbase.get(&timestamp) {
-            coinbase_price.checked_add(pyth_price).unwrap().checked_div(2).unwrap()
+            ProviderPrice {
+                price: coinbase_price.checked_add(pyth_price.price).unwrap().checked_div(2).unwrap(),
+                // widen the confidence to cover the spread between the sources
+                confidence: std::cmp::max(
+                    pyth_price.confidence,
+                    coinbase_price.abs_diff(pyth_price.price) / 2
+                ),
+                publish_time: pyth_price.publish_time,
+                source_bitmap: pyth_price.source_bitmap | SOURCE_COINBASE,
+                num_samples: 1,
+                candle: None,
+            }
         } else {
             pyth_price
         };
@@ -547,20 +1495,162 @@ impl OracleWorker {
         Ok(price)
     }
 
+    /// Resolve the price an order asks for, a candle if it has an end, a TWAP if it has a window
+    /// or the price at the timestamp, scaled to the market's decimals
+    async fn get_order_price(
+        &self,
+        market: &MarketType,
+        timestamp: i64,
+        twap_window: u32,
+        candle_end: i64
+    ) -> Result<ProviderPrice, SbError> {
+        // The market's decimals can be changed with update_market_config
+        let decimals = *self.market_decimals
+            .get(market)
+            .ok_or(SbError::Message("Market decimals not loaded"))?;
+
+        let price = if candle_end > 0 {
+            self.get_candle(market, timestamp, candle_end).await?
+        } else if twap_window > 0 {
+            self.get_twap(market, timestamp, twap_window).await?
+        } else {
+            self.get_price(market, timestamp).await?
+        };
+
+        price
+            .scale_to_decimals(decimals)
+            .ok_or(SbError::Message("Price overflows the market's decimals"))
+    }
+
+    /// Average the per-second prices over the window starting at the timestamp. Seconds missing
+    /// from the provider caches are fetched and any that can't be resolved are left out of the
+    /// sample count.
+    async fn get_twap(
+        &self,
+        market: &MarketType,
+        timestamp: i64,
+        window: u32
+    ) -> Result<ProviderPrice, SbError> {
+        let prices: Vec<ProviderPrice> = futures::stream
+            ::iter((0..window as i64).map(|offset| self.get_price(market, timestamp + offset)))
+            .buffered(MAX_CONCURRENT_PRICE_FETCHES)
+            .collect::<Vec<_>>().await
+            .into_iter()
+            .filter_map(|price| price.ok())
+            .collect();
+
+        if prices.is_empty() {
+            return Err(SbError::Message("No prices found for the TWAP window"));
+        }
+
+        let num_samples = prices.len() as u128;
+        let average = |value: fn(&ProviderPrice) -> u64| -> u64 {
+            (prices
+                .iter()
+                .map(|p| value(p) as u128)
+                .sum::<u128>() / num_samples) as u64
+        };
+
+        Ok(ProviderPrice {
+            price: average(|p| p.price),
+            confidence: average(|p| p.confidence),
+            publish_time: prices
+                .iter()
+                .map(|p| p.publish_time)
+                .max()
+                .unwrap(),
+            source_bitmap: prices.iter().fold(0, |bitmap, p| bitmap | p.source_bitmap),
+            num_samples: num_samples as u32,
+            candle: None,
+        })
+    }
+
+    /// Build the candle over [start, end] from the per-second Pyth prices, averaged with the
+    /// candle built from the Coinbase ticks when the websocket saw trades in the interval. The
+    /// sample count is the number of seconds Pyth resolved a price for.
+    async fn get_candle(
+        &self,
+        market: &MarketType,
+        start: i64,
+        end: i64
+    ) -> Result<ProviderPrice, SbError> {
+        let (pyth, coinbase_candles) = match market {
+            MarketType::Btc => (&self.pyth.btc, &self.coinbase.btc_candles),
+            MarketType::Eth => (&self.pyth.eth, &self.coinbase.eth_candles),
+            MarketType::Sol => (&self.pyth.sol, &self.coinbase.sol_candles),
+        };
+
+        // Buffered in order since the candle's open and close are the first and last prices
+        let prices: Vec<ProviderPrice> = futures::stream
+            ::iter((start..=end).map(|timestamp| pyth.get(timestamp)))
+            .buffered(MAX_CONCURRENT_PRICE_FETCHES)
+            .collect::<Vec<_>>().await
+            .into_iter()
+            .filter_map(|price| price.ok())
+            .collect();
+
+        let last = *prices
+            .last()
+            .ok_or(SbError::Message("No prices found for the candle interval"))?;
+
+        let mut pyth_candle = CandleAggregator::new();
+        for price in prices.iter() {
+            pyth_candle.add_price(price.price);
+        }
+
+        let mut coinbase_candle = CandleAggregator::new();
+        for timestamp in start..=end {
+            if let Some(candle) = coinbase_candles.get(&timestamp) {
+                coinbase_candle.add_candle(candle);
+            }
+        }
+
+        let mut candle = pyth_candle.candle().unwrap();
+        let mut source_bitmap = prices.iter().fold(0, |bitmap, p| bitmap | p.source_bitmap);
+        if let Some(coinbase_candle) = coinbase_candle.candle() {
+            candle = average_candles(&candle, &coinbase_candle);
+            source_bitmap |= SOURCE_COINBASE;
+        }
+
+        Ok(ProviderPrice {
+            price: candle.close,
+            // the close is the order's price so keep the confidence of the closing second
+            confidence: last.confidence,
+            publish_time: last.publish_time,
+            source_bitmap,
+            num_samples: pyth_candle.num_samples,
+            candle: Some(candle),
+        })
+    }
+
     // Here we can wait and group ixns if we need to
+    #[allow(clippy::too_many_arguments)]
     async fn fulfill_order(
         &self,
         order_pubkey: Pubkey,
         market: MarketType,
-        timestamp: i64
+        authority: Pubkey,
+        timestamp: i64,
+        twap_window: u32,
+        candle_end: i64,
+        callback: Option<OrderCallback>
     ) -> Result<(), SbError> {
-        let price = self.get_price(&market, timestamp).await?;
+        let price = self.get_order_price(&market, timestamp, twap_window, candle_end).await?;
         let market_pubkey = *self.markets.get(&market).unwrap();
+        let order_queue_pubkey = *self.order_queues.get(&market).unwrap();
+        let price_history_pubkey = *self.price_histories.get(&market).unwrap();
 
         let mut ixn_data = get_ixn_discriminator("fulfill_order").to_vec();
         let ixn_params = FulfillOrderParams {
-            market,
-            price,
+            market: market.to_bytes(),
+            price: price.price,
+            publish_time: price.publish_time,
+            confidence: price.confidence,
+            num_sources: price.num_sources(),
+            source_bitmap: price.source_bitmap,
+            num_samples: price.num_samples,
+            candle: price.candle,
+            override_deviation: self.override_deviation(&order_pubkey, &market),
         };
         ixn_data.append(&mut ixn_params.try_to_vec().unwrap());
 
@@ -568,22 +1658,47 @@ impl OracleWorker {
         let enclave_signer_pubkey = enclave_signer.pubkey();
         let signers = vec![self.payer.as_ref(), enclave_signer.deref()];
 
-        let msg = Message::new(
-            &[
-                Instruction {
-                    program_id: self.program_id,
-                    accounts: vec![
-                        AccountMeta::new(order_pubkey, false),
-                        AccountMeta::new_readonly(self.program_state_pubkey, false),
-                        AccountMeta::new_readonly(market_pubkey, false),
-                        AccountMeta::new(self.oracle_pubkey, false),
-                        AccountMeta::new_readonly(enclave_signer_pubkey, true)
-                    ],
-                    data: ixn_data,
-                },
-            ],
-            Some(&self.payer_pubkey)
-        );
+        let mut accounts = vec![
+            AccountMeta::new(order_pubkey, false),
+            AccountMeta::new_readonly(self.program_state_pubkey, false),
+            AccountMeta::new(market_pubkey, false),
+            AccountMeta::new(order_queue_pubkey, false),
+            AccountMeta::new(price_history_pubkey, false),
+            AccountMeta::new(self.rate_limit_pubkey(&market_pubkey, &authority), false),
+            AccountMeta::new(self.oracle_pubkey, false),
+            AccountMeta::new(self.stake_pubkey, false),
+            AccountMeta::new_readonly(enclave_signer_pubkey, true),
+            AccountMeta::new_readonly(self.event_authority_pubkey, false),
+            AccountMeta::new_readonly(self.program_id, false)
+        ];
+        let mut ixns = Vec::new();
+        // The program invokes the order's callback with these once the order settles
+        if let Some(callback) = callback {
+            let (callback_authority, _) = Pubkey::find_program_address(
+                &[CALLBACK_AUTHORITY_SEED, callback.program_id.as_ref()],
+                &self.program_id
+            );
+            accounts.push(AccountMeta::new_readonly(callback.program_id, false));
+            accounts.push(AccountMeta::new_readonly(callback_authority, false));
+            for account in callback.accounts.iter() {
+                if account.is_writable {
+                    accounts.push(AccountMeta::new(account.pubkey, false));
+                } else {
+                    accounts.push(AccountMeta::new_readonly(account.pubkey, false));
+                }
+            }
+
+            ixns.push(
+                ComputeBudgetInstruction::set_compute_unit_limit(FULFILL_ORDER_CALLBACK_COMPUTE_UNITS)
+            );
+        }
+        ixns.push(Instruction {
+            program_id: self.program_id,
+            accounts,
+            data: ixn_data,
+        });
+
+        let msg = Message::new(&ixns, Some(&self.payer_pubkey));
         let mut tx = Transaction::new_unsigned(msg);
 
         tx
@@ -593,12 +1708,20 @@ impl OracleWorker {
                 source: std::sync::Arc::new(e),
             })?;
 
-        let signature = self.rpc
-            .send_and_confirm_transaction(&tx).await
-            .map_err(|e| SbError::CustomError {
-                message: "Failed to send txn".into(),
-                source: std::sync::Arc::new(e),
-            })?;
+        let signature = match self.rpc.send_and_confirm_transaction(&tx).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                if format!("{:?}", e).contains(&BackfillOracleError::PriceDeviationExceeded.name()) {
+                    info!("[ORDER] order {} exceeded its price deviation bound", order_pubkey);
+                    self.deviation_rejected_orders.insert(order_pubkey);
+                }
+                return Err(SbError::CustomError {
+                    message: "Failed to send txn".into(),
+                    source: std::sync::Arc::new(e),
+                });
+            }
+        };
+        self.deviation_rejected_orders.remove(&order_pubkey);
 
         info!("[ORACLE] fulfill_order: {}", signature);
 
@@ -12,7 +12,15 @@ describe("backfill-oracle-program", () => {
 
   it("Is initialized!", async () => {
     // Add your test here.
-    const tx = await program.methods.initialize().rpc();
+    const tx = await program.methods
+      .initialize({
+        attestationValiditySlots: new anchor.BN(216_000),
+        oracleQuorum: 1,
+        orderCloseGracePeriod: new anchor.BN(3600),
+        disputeWindow: new anchor.BN(3600),
+        minStake: new anchor.BN(100_000_000),
+      })
+      .rpc();
     console.log("Your transaction signature", tx);
   });
 });